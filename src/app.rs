@@ -1,135 +1,1484 @@
-use crate::config::SmtpConfig;
-use crate::email::{send_bulk, send_single, SendProgress};
-use crate::template::{self, EmailTemplate, Recipient};
+use crate::config::{OAuth2Settings, OAuthProvider, Settings, SmtpConfig, TlsMode};
+use crate::contacts::{self, ColumnMapping, CsvTable};
+use crate::email::{
+    dry_run, estimated_attachments_size, format_size, send_bulk, send_single, test_connection,
+    DryRunManifest, SendControl, SendProgress, TestStep,
+};
+use crate::oauth;
+use crate::paths::Paths;
+use crate::plaintext::{attribute, decode_entities, html_to_text};
+use crate::richtext;
+use crate::secret::Secret;
+use crate::template::{
+    self, parse_address_list, BodyFormat, CustomHeader, EmailTemplate, InlineImage, LoadError,
+    Recipient, SendStatus,
+};
+use crate::validate::{validate, ValidationReport};
 use eframe::egui;
+use lettre::message::Mailbox;
 use std::collections::HashMap;
-use std::sync::mpsc::{self, Receiver};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// How long editing has to pause before template edits are written to disk.
+const SAVE_DELAY: Duration = Duration::from_secs(1);
 
 pub struct EmailApp {
-    config: SmtpConfig,
+    settings: Settings,
+    paths: Paths,
+    /// First-run setup, shown instead of everything else until saved.
+    setup: Option<ProfileForm>,
+    /// A templates.json that failed to load, shown instead of everything
+    /// else until the user restores a backup or starts over.
+    recovery: Option<Recovery>,
     templates: Vec<EmailTemplate>,
+    /// Why the last save of the templates failed, until one succeeds.
+    save_error: Option<String>,
+    /// When the templates were last edited without being saved yet.
+    unsaved_since: Option<Instant>,
     selected_template: Option<usize>,
 
     // Editing state for new recipient
     new_recipient_email: String,
+    new_recipient_name: String,
+    new_recipient_cc: String,
+    new_recipient_bcc: String,
     new_recipient_args: HashMap<String, String>,
 
     // New template name
     new_template_name: String,
 
+    // Attachment path with placeholders, being typed
+    new_attachment_pattern: String,
+
     // Sending state
     progress_rx: Option<Receiver<SendProgress>>,
+    sending_template_id: Option<String>,
+    control_tx: Option<Sender<SendControl>>,
     is_sending: bool,
+    send_job: SendJob,
+    is_paused: bool,
     status_log: Vec<String>,
 
     // Confirmation dialog
     show_confirm_dialog: bool,
+    /// Whether the confirmed send covers only recipients not yet sent to.
+    confirm_unsent_only: bool,
+    /// Validation of the pending bulk send, computed when the dialog opens.
+    confirm_report: Option<ValidationReport>,
 
     // Preview state
     preview_recipient_idx: Option<usize>,
+
+    // CSV import mapping dialog
+    csv_import: Option<CsvImport>,
+
+    // Link URL prompt for the body editor
+    link_prompt: Option<LinkPrompt>,
+
+    // OAuth2 sign-in running in the background, reporting (profile, result)
+    sign_in_rx: Option<Receiver<(String, Result<(), String>)>>,
+    /// Whether each OAuth2 profile has stored tokens, by profile name.
+    signed_in: HashMap<String, bool>,
+
+    // Settings window
+    settings_editor: Option<SettingsEditor>,
+}
+
+/// What the background sender is busy with while `is_sending`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SendJob {
+    Bulk,
+    Single,
+    /// A dry run, with the number of messages written so far.
+    DryRun { written: usize },
+}
+
+/// The templates file couldn't be loaded; what the user can do about it.
+struct Recovery {
+    problem: LoadError,
+    /// Why the chosen way out failed.
+    error: Option<String>,
+}
+
+/// A CSV file waiting for the user to confirm its column mapping.
+struct CsvImport {
+    template_id: String,
+    file_name: String,
+    table: CsvTable,
+    mapping: ColumnMapping,
+}
+
+/// A link being inserted into a template body, waiting for its URL.
+struct LinkPrompt {
+    template_id: String,
+    /// Body selection (in characters) the link replaces.
+    selection: std::ops::Range<usize>,
+    url: String,
+}
+
+/// The settings window: a form per profile, applied only when saved.
+struct SettingsEditor {
+    forms: Vec<ProfileForm>,
+    selected: usize,
+    /// Why the last save failed.
+    error: Option<String>,
+    /// Connection test of the selected profile, running in the background.
+    test_rx: Option<Receiver<TestStep>>,
+    test_steps: Vec<TestStep>,
+}
+
+impl SettingsEditor {
+    fn new(settings: &Settings) -> Self {
+        Self {
+            forms: settings.profiles.iter().map(ProfileForm::from_config).collect(),
+            selected: 0,
+            error: None,
+            test_rx: None,
+            test_steps: Vec::new(),
+        }
+    }
+
+    /// Tests the selected profile as currently filled in, saved or not.
+    fn start_test(&mut self) {
+        let form = &mut self.forms[self.selected];
+        self.test_steps.clear();
+        let config = match form.to_config() {
+            Ok(config) => config,
+            Err(e) => {
+                form.error = Some(e);
+                return;
+            }
+        };
+        form.error = None;
+        let (tx, rx) = mpsc::channel();
+        self.test_rx = Some(rx);
+        std::thread::spawn(move || test_connection(&config, &tx));
+    }
+
+    fn poll_test(&mut self) {
+        let Some(rx) = &self.test_rx else {
+            return;
+        };
+        loop {
+            match rx.try_recv() {
+                Ok(step) => self.test_steps.push(step),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.test_rx = None;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// How a profile being set up authenticates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AuthChoice {
+    None,
+    Password,
+    Env,
+    File,
+    Command,
+    Keyring,
+    OAuth2,
+}
+
+impl AuthChoice {
+    const ALL: [AuthChoice; 7] = [
+        AuthChoice::Keyring,
+        AuthChoice::Env,
+        AuthChoice::File,
+        AuthChoice::Command,
+        AuthChoice::OAuth2,
+        AuthChoice::Password,
+        AuthChoice::None,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            AuthChoice::None => "No authentication",
+            AuthChoice::Password => "Password in the settings file (not recommended)",
+            AuthChoice::Env => "Password from environment variable",
+            AuthChoice::File => "Password from file",
+            AuthChoice::Command => "Password from command",
+            AuthChoice::Keyring => "Password from OS keyring",
+            AuthChoice::OAuth2 => "OAuth2 sign-in",
+        }
+    }
+
+    /// Label and hint for the text field the choice needs, if any.
+    fn input(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            AuthChoice::Password => Some(("Password:", "")),
+            AuthChoice::Env => Some(("Variable:", "SMTP_PASSWORD")),
+            AuthChoice::File => Some(("File:", "/home/you/.config/email-senderr/password")),
+            AuthChoice::Command => Some(("Command:", "pass show smtp/work")),
+            AuthChoice::None | AuthChoice::Keyring | AuthChoice::OAuth2 => None,
+        }
+    }
+}
+
+/// A sender profile being filled in, kept as text until it is saved.
+struct ProfileForm {
+    /// The profile being edited, if any; settings the form has no field for
+    /// (transport, DKIM) are carried over from it.
+    base: Option<SmtpConfig>,
+    name: String,
+    host: String,
+    port: String,
+    tls: TlsMode,
+    username: String,
+    from_name: String,
+    from_address: String,
+    auth: AuthChoice,
+    /// Password, variable name, file path or command, depending on `auth`.
+    secret: String,
+    provider: OAuthProvider,
+    client_id: String,
+    client_secret: String,
+    tenant: String,
+    auth_url: String,
+    token_url: String,
+    scope: String,
+    send_delay_ms: String,
+    max_retries: String,
+    retry_delay_ms: String,
+    retry_max_delay_ms: String,
+    attachment_limit_mb: String,
+    error: Option<String>,
+}
+
+impl Default for ProfileForm {
+    fn default() -> Self {
+        let defaults = SmtpConfig::new(String::new(), String::new(), String::new(), String::new());
+        Self {
+            base: None,
+            name: "Default".to_string(),
+            host: String::new(),
+            port: String::new(),
+            tls: TlsMode::default(),
+            username: String::new(),
+            from_name: String::new(),
+            from_address: String::new(),
+            // Keeps the password out of the settings file
+            auth: AuthChoice::Keyring,
+            secret: String::new(),
+            provider: OAuthProvider::default(),
+            client_id: String::new(),
+            client_secret: String::new(),
+            tenant: String::new(),
+            auth_url: String::new(),
+            token_url: String::new(),
+            scope: String::new(),
+            send_delay_ms: defaults.send_delay_ms.to_string(),
+            max_retries: defaults.max_retries.to_string(),
+            retry_delay_ms: defaults.retry_delay_ms.to_string(),
+            retry_max_delay_ms: defaults.retry_max_delay_ms.to_string(),
+            attachment_limit_mb: defaults.attachment_limit_mb.to_string(),
+            error: None,
+        }
+    }
+}
+
+impl ProfileForm {
+    /// A form for editing an existing profile.
+    fn from_config(config: &SmtpConfig) -> Self {
+        let text = |value: &Option<String>| value.clone().unwrap_or_default();
+        let (auth, secret) = if config.oauth2.is_some() {
+            (AuthChoice::OAuth2, String::new())
+        } else if let Some(name) = &config.password_env {
+            (AuthChoice::Env, name.clone())
+        } else if let Some(path) = &config.password_file {
+            (AuthChoice::File, path.display().to_string())
+        } else if let Some(command) = &config.password_command {
+            (AuthChoice::Command, command.clone())
+        } else if config.password_keyring {
+            (AuthChoice::Keyring, String::new())
+        } else if !config.password.is_empty() {
+            (AuthChoice::Password, config.password.expose().to_string())
+        } else {
+            (AuthChoice::None, String::new())
+        };
+        let mut form = Self {
+            base: Some(config.clone()),
+            name: config.name.clone(),
+            host: config.host.clone(),
+            port: config.port.map(|p| p.to_string()).unwrap_or_default(),
+            tls: config.tls,
+            username: config.username.clone(),
+            from_name: config.from_name.clone(),
+            from_address: text(&config.from_address),
+            auth,
+            secret,
+            send_delay_ms: config.send_delay_ms.to_string(),
+            max_retries: config.max_retries.to_string(),
+            retry_delay_ms: config.retry_delay_ms.to_string(),
+            retry_max_delay_ms: config.retry_max_delay_ms.to_string(),
+            attachment_limit_mb: config.attachment_limit_mb.to_string(),
+            ..Self::default()
+        };
+        if let Some(oauth2) = &config.oauth2 {
+            form.provider = oauth2.provider;
+            form.client_id = oauth2.client_id.clone();
+            form.client_secret = oauth2
+                .client_secret
+                .as_ref()
+                .map(|s| s.expose().to_string())
+                .unwrap_or_default();
+            form.tenant = text(&oauth2.tenant);
+            form.auth_url = text(&oauth2.auth_url);
+            form.token_url = text(&oauth2.token_url);
+            form.scope = text(&oauth2.scope);
+        }
+        form
+    }
+
+    /// Builds and validates the profile.
+    fn to_config(&self) -> Result<SmtpConfig, String> {
+        let required = [
+            ("Profile name", &self.name),
+            ("SMTP host", &self.host),
+            ("Username", &self.username),
+            ("From name", &self.from_name),
+        ];
+        if let Some((field, _)) = required.iter().find(|(_, value)| value.trim().is_empty()) {
+            return Err(format!("{} is required", field));
+        }
+        let optional = |text: &str| Some(text.trim().to_string()).filter(|t| !t.is_empty());
+
+        let number = |label: &str, text: &str| {
+            text.trim()
+                .parse::<u64>()
+                .map_err(|_| format!("{} must be a whole number", label))
+        };
+
+        let mut config = SmtpConfig::new(
+            self.name.trim().to_string(),
+            self.host.trim().to_string(),
+            self.username.trim().to_string(),
+            self.from_name.trim().to_string(),
+        );
+        if let Some(base) = &self.base {
+            config.transport = base.transport.clone();
+            config.dkim = base.dkim.clone();
+        }
+        config.tls = self.tls;
+        config.port = match optional(&self.port) {
+            Some(port) => Some(port.parse().map_err(|_| format!("'{}' is not a valid port", port))?),
+            None => None,
+        };
+        config.from_address = optional(&self.from_address);
+        config.send_delay_ms = number("Delay between emails", &self.send_delay_ms)?;
+        config.max_retries = number("Retries", &self.max_retries)?
+            .try_into()
+            .map_err(|_| "Retries is too large".to_string())?;
+        config.retry_delay_ms = number("First retry delay", &self.retry_delay_ms)?;
+        config.retry_max_delay_ms = number("Longest retry delay", &self.retry_max_delay_ms)?;
+        config.attachment_limit_mb = number("Attachment limit", &self.attachment_limit_mb)?;
+
+        if self.auth.input().is_some() && self.secret.trim().is_empty() {
+            return Err(format!("{} is required", self.auth.label()));
+        }
+        match self.auth {
+            AuthChoice::None => {}
+            AuthChoice::Password => config.password = Secret::new(self.secret.clone()),
+            AuthChoice::Env => config.password_env = optional(&self.secret),
+            AuthChoice::File => config.password_file = optional(&self.secret).map(Into::into),
+            AuthChoice::Command => config.password_command = optional(&self.secret),
+            AuthChoice::Keyring => config.password_keyring = true,
+            AuthChoice::OAuth2 => {
+                config.oauth2 = Some(OAuth2Settings {
+                    provider: self.provider,
+                    client_id: self.client_id.trim().to_string(),
+                    client_secret: optional(&self.client_secret).map(Secret::new),
+                    tenant: optional(&self.tenant),
+                    auth_url: optional(&self.auth_url),
+                    token_url: optional(&self.token_url),
+                    scope: optional(&self.scope),
+                })
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn show(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("profile_form")
+            .num_columns(2)
+            .spacing([10.0, 6.0])
+            .show(ui, |ui| {
+                let text_row = |ui: &mut egui::Ui, label: &str, value: &mut String, hint: &str| {
+                    ui.label(label);
+                    ui.add(egui::TextEdit::singleline(value).desired_width(320.0).hint_text(hint));
+                    ui.end_row();
+                };
+                text_row(ui, "Profile name:", &mut self.name, "");
+                text_row(ui, "SMTP host:", &mut self.host, "smtp.gmail.com");
+
+                ui.label("Security:");
+                egui::ComboBox::from_id_salt("setup_tls")
+                    .selected_text(self.tls.label())
+                    .show_ui(ui, |ui| {
+                        for tls in TlsMode::ALL {
+                            ui.selectable_value(&mut self.tls, tls, tls.label());
+                        }
+                    });
+                ui.end_row();
+
+                let default_port = self.tls.default_port().to_string();
+                text_row(ui, "Port:", &mut self.port, &default_port);
+                text_row(ui, "Username:", &mut self.username, "you@example.com");
+                text_row(ui, "From name:", &mut self.from_name, "Your Name");
+                text_row(ui, "From address:", &mut self.from_address, "same as username");
+
+                ui.label("Authentication:");
+                egui::ComboBox::from_id_salt("setup_auth")
+                    .selected_text(self.auth.label())
+                    .show_ui(ui, |ui| {
+                        for auth in AuthChoice::ALL {
+                            ui.selectable_value(&mut self.auth, auth, auth.label());
+                        }
+                    });
+                ui.end_row();
+
+                if let Some((label, hint)) = self.auth.input() {
+                    ui.label(label);
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.secret)
+                            .password(self.auth == AuthChoice::Password)
+                            .desired_width(320.0)
+                            .hint_text(hint),
+                    );
+                    ui.end_row();
+                }
+                if self.auth == AuthChoice::Password {
+                    ui.label("");
+                    ui.colored_label(
+                        egui::Color32::from_rgb(220, 80, 80),
+                        "⚠ Stored as plain text. The keyring, a file or an environment variable keep it out of the settings file.",
+                    );
+                    ui.end_row();
+                }
+                if self.auth == AuthChoice::Keyring {
+                    ui.label("");
+                    ui.label(format!(
+                        "Looked up under service '{}' and the username as account.",
+                        crate::secret::KEYRING_SERVICE
+                    ));
+                    ui.end_row();
+                }
+                if self.auth == AuthChoice::OAuth2 {
+                    ui.label("Provider:");
+                    egui::ComboBox::from_id_salt("setup_provider")
+                        .selected_text(self.provider.label())
+                        .show_ui(ui, |ui| {
+                            for provider in OAuthProvider::ALL {
+                                ui.selectable_value(&mut self.provider, provider, provider.label());
+                            }
+                        });
+                    ui.end_row();
+                    text_row(ui, "Client ID:", &mut self.client_id, "");
+                    ui.label("Client secret:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut self.client_secret)
+                            .password(true)
+                            .desired_width(320.0)
+                            .hint_text("if the provider issued one"),
+                    );
+                    ui.end_row();
+                    if self.provider == OAuthProvider::Microsoft {
+                        text_row(ui, "Tenant:", &mut self.tenant, "common");
+                    }
+                    if self.provider == OAuthProvider::Custom {
+                        text_row(ui, "Authorization URL:", &mut self.auth_url, "https://");
+                        text_row(ui, "Token URL:", &mut self.token_url, "https://");
+                        text_row(ui, "Scope:", &mut self.scope, "");
+                    }
+                }
+            });
+
+        egui::CollapsingHeader::new("Sending")
+            .id_salt("profile_form_sending")
+            .show(ui, |ui| {
+                egui::Grid::new("profile_form_sending_grid")
+                    .num_columns(2)
+                    .spacing([10.0, 6.0])
+                    .show(ui, |ui| {
+                        let number_row = |ui: &mut egui::Ui, label: &str, value: &mut String, unit: &str| {
+                            ui.label(label);
+                            ui.horizontal(|ui| {
+                                ui.add(egui::TextEdit::singleline(value).desired_width(80.0));
+                                ui.label(unit);
+                            });
+                            ui.end_row();
+                        };
+                        number_row(ui, "Delay between emails:", &mut self.send_delay_ms, "ms");
+                        number_row(ui, "Retries:", &mut self.max_retries, "per recipient");
+                        number_row(ui, "First retry delay:", &mut self.retry_delay_ms, "ms, doubling each retry");
+                        number_row(ui, "Longest retry delay:", &mut self.retry_max_delay_ms, "ms");
+                        number_row(ui, "Attachment limit:", &mut self.attachment_limit_mb, "MB (0 for none)");
+                    });
+            });
+    }
 }
 
 impl EmailApp {
-    pub fn new(config: SmtpConfig, templates: Vec<EmailTemplate>) -> Self {
+    pub fn new(
+        settings: Option<Settings>,
+        templates: Result<Vec<EmailTemplate>, LoadError>,
+        paths: Paths,
+        status_log: Vec<String>,
+    ) -> Self {
+        let (templates, recovery) = match templates {
+            Ok(templates) => (templates, None),
+            Err(problem) => (Vec::new(), Some(Recovery { problem, error: None })),
+        };
         Self {
-            config,
+            setup: settings.is_none().then(ProfileForm::default),
+            settings: settings.unwrap_or(Settings { profiles: Vec::new() }),
+            paths,
+            recovery,
             templates,
+            save_error: None,
+            unsaved_since: None,
             selected_template: None,
             new_recipient_email: String::new(),
+            new_recipient_name: String::new(),
+            new_recipient_cc: String::new(),
+            new_recipient_bcc: String::new(),
             new_recipient_args: HashMap::new(),
             new_template_name: String::new(),
+            new_attachment_pattern: String::new(),
             progress_rx: None,
+            sending_template_id: None,
+            control_tx: None,
             is_sending: false,
-            status_log: Vec::new(),
+            send_job: SendJob::Bulk,
+            is_paused: false,
+            status_log,
             show_confirm_dialog: false,
+            confirm_unsent_only: false,
+            confirm_report: None,
             preview_recipient_idx: None,
+            csv_import: None,
+            link_prompt: None,
+            sign_in_rx: None,
+            signed_in: HashMap::new(),
+            settings_editor: None,
+        }
+    }
+
+    /// The first-run wizard: asks for a sender profile and writes the
+    /// settings file before the rest of the app is usable.
+    fn show_setup(&mut self, ctx: &egui::Context) {
+        let mut save = false;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Some(form) = &mut self.setup else {
+                return;
+            };
+            ui.heading("Welcome to Bulk Email Sender");
+            ui.label("Set up the account your emails are sent from. You can add more profiles later.");
+            ui.add_space(10.0);
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                form.show(ui);
+                ui.add_space(10.0);
+                ui.label(
+                    egui::RichText::new(format!(
+                        "Settings will be saved to {}",
+                        self.paths.config_file.display()
+                    ))
+                    .color(egui::Color32::from_rgb(150, 150, 150)),
+                );
+                if let Some(error) = &form.error {
+                    ui.colored_label(egui::Color32::from_rgb(220, 80, 80), error);
+                }
+                ui.add_space(5.0);
+                if ui.button("  Save and Continue  ").clicked() {
+                    save = true;
+                }
+            });
+        });
+
+        if save && let Some(form) = &mut self.setup {
+            let result = form.to_config().and_then(|config| {
+                let settings = Settings { profiles: vec![config] };
+                settings.save(&self.paths.config_file)?;
+                Ok(settings)
+            });
+            match result {
+                Ok(settings) => {
+                    self.status_log.push(format!(
+                        "✓ Saved settings to {}",
+                        self.paths.config_file.display()
+                    ));
+                    self.settings = settings;
+                    self.setup = None;
+                }
+                Err(e) => form.error = Some(e),
+            }
+        }
+    }
+
+    /// Saves the profiles from the settings window and closes it, moving
+    /// templates that used a renamed profile over to the new name. On an
+    /// error the window stays open with the error shown.
+    fn save_settings_editor(&mut self) {
+        let Some(editor) = &mut self.settings_editor else {
+            return;
+        };
+        editor.error = None;
+        let mut profiles = Vec::new();
+        for (i, form) in editor.forms.iter_mut().enumerate() {
+            match form.to_config() {
+                Ok(config) => {
+                    form.error = None;
+                    profiles.push(config);
+                }
+                Err(e) => {
+                    form.error = Some(e);
+                    editor.selected = i;
+                    return;
+                }
+            }
+        }
+        let settings = Settings { profiles };
+        if let Err(e) = settings.save(&self.paths.config_file) {
+            editor.error = Some(e);
+            return;
+        }
+
+        let renamed: Vec<(String, String)> = editor
+            .forms
+            .iter()
+            .zip(&settings.profiles)
+            .filter_map(|(form, config)| {
+                let old = &form.base.as_ref()?.name;
+                (*old != config.name).then(|| (old.clone(), config.name.clone()))
+            })
+            .collect();
+        let mut retargeted = false;
+        for t in &mut self.templates {
+            if let Some(profile) = &t.sender_profile
+                && let Some((_, new)) = renamed.iter().find(|(old, _)| old == profile)
+            {
+                t.sender_profile = Some(new.clone());
+                retargeted = true;
+            }
+        }
+        if retargeted {
+            self.save_templates();
+        }
+
+        self.status_log.push(format!(
+            "✓ Saved settings to {}",
+            self.paths.config_file.display()
+        ));
+        self.settings = settings;
+        self.signed_in.clear();
+        self.settings_editor = None;
+    }
+
+    /// Shows the load error and the ways out: restore the newest backup,
+    /// start with no templates, or quit and repair the file by hand. Either
+    /// way in keeps the damaged file under another name.
+    fn show_recovery(&mut self, ctx: &egui::Context) {
+        let mut restore = false;
+        let mut start_over = false;
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let Some(recovery) = &self.recovery else {
+                return;
+            };
+            ui.heading("Your templates could not be loaded");
+            ui.add_space(5.0);
+            ui.colored_label(egui::Color32::from_rgb(220, 80, 80), &recovery.problem.error);
+            ui.add_space(10.0);
+            ui.label("The file won't be overwritten. Choose how to continue; the damaged file is kept under a new name either way.");
+            ui.add_space(10.0);
+            if let Some(backup) = &recovery.problem.backup {
+                let taken = backup
+                    .taken_at
+                    .map(|secs| format!(" from {}", format_timestamp(secs)))
+                    .unwrap_or_default();
+                if ui
+                    .button(format!(
+                        "  Restore Backup{} ({} template(s))  ",
+                        taken,
+                        backup.templates.len()
+                    ))
+                    .on_hover_text(backup.path.display().to_string())
+                    .clicked()
+                {
+                    restore = true;
+                }
+            } else {
+                ui.label(
+                    egui::RichText::new("No usable backup was found.")
+                        .color(egui::Color32::from_rgb(150, 150, 150)),
+                );
+            }
+            if ui.button("  Start with No Templates  ").clicked() {
+                start_over = true;
+            }
+            if ui.button("  Quit  ").on_hover_text("Leave everything as it is").clicked() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            if let Some(error) = &recovery.error {
+                ui.add_space(5.0);
+                ui.colored_label(egui::Color32::from_rgb(220, 80, 80), error);
+            }
+        });
+
+        if !(restore || start_over) {
+            return;
+        }
+        let Some(recovery) = &mut self.recovery else {
+            return;
+        };
+        let aside = match template::set_aside_templates() {
+            Ok(aside) => aside,
+            Err(e) => {
+                recovery.error = Some(e);
+                return;
+            }
+        };
+        if restore && let Some(backup) = recovery.problem.backup.take() {
+            self.status_log.push(format!(
+                "✓ Restored {} template(s) from {}",
+                backup.templates.len(),
+                backup.path.display()
+            ));
+            self.templates = backup.templates;
+            self.save_templates();
+        }
+        self.status_log.push(format!(
+            "— The damaged templates file was kept as {}",
+            aside.display()
+        ));
+        self.recovery = None;
+    }
+
+    /// Saves the templates, logging a failure once rather than on every
+    /// attempt, and noting when saving works again.
+    fn save_templates(&mut self) {
+        self.unsaved_since = None;
+        match template::save_templates(&self.templates) {
+            Ok(()) => {
+                if self.save_error.take().is_some() {
+                    self.status_log.push("✓ Templates saved again.".to_string());
+                }
+            }
+            Err(e) => {
+                if self.save_error.as_ref() != Some(&e) {
+                    self.status_log.push(format!("✗ Templates not saved: {}", e));
+                }
+                self.save_error = Some(e);
+            }
+        }
+    }
+
+    /// Notes an edit to be saved once editing pauses for `SAVE_DELAY`, so
+    /// typing doesn't rewrite the whole file on every keystroke.
+    fn templates_edited(&mut self) {
+        self.unsaved_since = Some(Instant::now());
+    }
+
+    /// Saves pending edits once editing has paused, or schedules a repaint
+    /// for when it will have.
+    fn save_when_idle(&mut self, ctx: &egui::Context) {
+        if let Some(since) = self.unsaved_since {
+            let wait = SAVE_DELAY.saturating_sub(since.elapsed());
+            if wait.is_zero() {
+                self.save_templates();
+            } else {
+                ctx.request_repaint_after(wait);
+            }
+        }
+    }
+
+    /// The sender profile a template sends from.
+    fn config_for(&self, template: &EmailTemplate) -> Result<SmtpConfig, String> {
+        self.settings
+            .profile(template.sender_profile.as_deref())
+            .cloned()
+    }
+
+    /// Records a recipient's delivery state on the template being sent and
+    /// persists it, so progress survives a crash or restart.
+    fn record_status(&mut self, index: usize, email: &str, status: SendStatus) {
+        let Some(id) = &self.sending_template_id else {
+            return;
+        };
+        if let Some(recipient) = self
+            .templates
+            .iter_mut()
+            .find(|t| &t.id == id)
+            .and_then(|t| t.recipients.get_mut(index))
+            .filter(|r| r.email == email)
+        {
+            recipient.status = status;
+            self.save_templates();
+        }
+    }
+
+    fn start_csv_import(&mut self, template: &EmailTemplate, path: &std::path::Path) {
+        match contacts::read_csv(path) {
+            Ok(table) => {
+                let mapping = ColumnMapping::guess(&table.headers, &template.extract_placeholders());
+                self.csv_import = Some(CsvImport {
+                    template_id: template.id.clone(),
+                    file_name: path
+                        .file_name()
+                        .map(|n| n.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    table,
+                    mapping,
+                });
+            }
+            Err(e) => {
+                self.status_log.push(format!("✗ Could not read {}: {}", path.display(), e));
+            }
+        }
+    }
+
+    fn finish_csv_import(&mut self, import: CsvImport) {
+        let (recipients, mapping_errors) = contacts::map_rows(&import.table, &import.mapping);
+        for error in import.table.errors.iter().chain(&mapping_errors) {
+            self.status_log
+                .push(format!("✗ {}: {}", import.file_name, error));
+        }
+        let count = recipients.len();
+        if let Some(t) = self.templates.iter_mut().find(|t| t.id == import.template_id) {
+            t.recipients.extend(recipients);
+            self.save_templates();
         }
+        self.status_log.push(format!(
+            "— Imported {} recipient(s) from {} ({} row(s) skipped).",
+            count,
+            import.file_name,
+            import.table.errors.len() + mapping_errors.len()
+        ));
+    }
+
+    /// Marks `template` as busy with `job` and returns the channel the
+    /// background thread reports on.
+    fn begin_job(&mut self, template: &EmailTemplate, job: SendJob) -> Sender<SendProgress> {
+        let (tx, rx) = mpsc::channel();
+        self.progress_rx = Some(rx);
+        self.sending_template_id = Some(template.id.clone());
+        self.is_sending = true;
+        self.send_job = job;
+        tx
+    }
+
+    fn start_bulk_send(&mut self, idx: usize, targets: Vec<usize>) {
+        let template = self.templates[idx].clone();
+        let config = match self.config_for(&template) {
+            Ok(config) => config,
+            Err(e) => {
+                self.status_log.push(format!("✗ Bulk send not started: {}", e));
+                return;
+            }
+        };
+        let (control_tx, control_rx) = mpsc::channel();
+        let tx = self.begin_job(&template, SendJob::Bulk);
+        self.control_tx = Some(control_tx);
+        self.status_log
+            .push(format!("— Starting bulk send for '{}'...", template.name));
+        send_bulk(config, template, targets, tx, control_rx);
+    }
+
+    fn start_single_send(&mut self, template: &EmailTemplate, index: usize) {
+        let config = match self.config_for(template) {
+            Ok(config) => config,
+            Err(e) => {
+                self.status_log.push(format!("✗ Not sent: {}", e));
+                return;
+            }
+        };
+        let tx = self.begin_job(template, SendJob::Single);
+        send_single(config, template.clone(), index, tx);
+    }
+
+    fn finish_sending(&mut self) {
+        self.sending_template_id = None;
+        self.is_sending = false;
+        self.is_paused = false;
+        self.progress_rx = None;
+        self.control_tx = None;
     }
 
-    fn save_templates(&self) {
-        template::save_templates(&self.templates);
+    fn send_control(&mut self, command: SendControl) {
+        if let Some(tx) = &self.control_tx {
+            let _ = tx.send(command);
+        }
+    }
+
+    fn run_dry_run(&mut self, template: &EmailTemplate, dir: &std::path::Path) {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let out_dir = dir.join(format!("dry-run-{}", stamp));
+        let config = match self.config_for(template) {
+            Ok(config) => config,
+            Err(e) => {
+                self.status_log.push(format!("✗ Dry run failed: {}", e));
+                return;
+            }
+        };
+        let tx = self.begin_job(template, SendJob::DryRun { written: 0 });
+        dry_run(config, template.clone(), out_dir, tx);
+    }
+
+    fn finish_dry_run(&mut self, out_dir: &std::path::Path, result: Result<DryRunManifest, String>) {
+        match result {
+            Ok(manifest) => {
+                for entry in &manifest.messages {
+                    if let Some(error) = &entry.error {
+                        self.status_log.push(format!(
+                            "✗ [{}] Could not build message for {}: {}",
+                            entry.index + 1,
+                            entry.recipient,
+                            error
+                        ));
+                    } else if !entry.unresolved_placeholders.is_empty() {
+                        self.status_log.push(format!(
+                            "✗ [{}] {} has unresolved placeholders: {}",
+                            entry.index + 1,
+                            entry.recipient,
+                            entry.unresolved_placeholders.join(", ")
+                        ));
+                    }
+                }
+                self.status_log.push(format!(
+                    "— Dry run wrote {} message(s) to {}",
+                    manifest.messages.iter().filter(|e| e.file.is_some()).count(),
+                    out_dir.display()
+                ));
+            }
+            Err(e) => {
+                self.status_log.push(format!("✗ Dry run failed: {}", e));
+            }
+        }
+    }
+
+    /// Starts the browser sign-in for an OAuth2 profile on a background thread.
+    fn start_sign_in(&mut self, config: SmtpConfig) {
+        let (tx, rx) = mpsc::channel();
+        self.sign_in_rx = Some(rx);
+        self.status_log.push(format!(
+            "— Signing in '{}' in the browser...",
+            config.name
+        ));
+        std::thread::spawn(move || {
+            let result = oauth::sign_in(&config);
+            let _ = tx.send((config.name, result));
+        });
+    }
+
+    fn poll_sign_in(&mut self) {
+        let Some(rx) = &self.sign_in_rx else {
+            return;
+        };
+        let Ok((profile, result)) = rx.try_recv() else {
+            return;
+        };
+        self.sign_in_rx = None;
+        match result {
+            Ok(()) => {
+                self.status_log.push(format!("✓ Signed in '{}'.", profile));
+                self.signed_in.insert(profile, true);
+            }
+            Err(e) => self
+                .status_log
+                .push(format!("✗ Sign-in for '{}' failed: {}", profile, e)),
+        }
     }
 
     fn poll_progress(&mut self) {
-        if let Some(rx) = &self.progress_rx {
-            while let Ok(msg) = rx.try_recv() {
-                match msg {
-                    SendProgress::Sent { index, email } => {
-                        self.status_log
-                            .push(format!("✓ [{}] Sent to {}", index + 1, email));
-                    }
-                    SendProgress::Failed { index, email, error } => {
-                        self.status_log
-                            .push(format!("✗ [{}] Failed to send to {}: {}", index + 1, email, error));
-                    }
-                    SendProgress::Done => {
+        let Some(rx) = &self.progress_rx else {
+            return;
+        };
+        let messages: Vec<SendProgress> = rx.try_iter().collect();
+        for msg in messages {
+            match msg {
+                SendProgress::Sent { index, email, message_id } => {
+                    self.status_log
+                        .push(format!("✓ [{}] Sent to {}", index + 1, email));
+                    self.record_status(
+                        index,
+                        &email,
+                        SendStatus::Sent { at: now_secs(), message_id },
+                    );
+                }
+                SendProgress::Failed { index, email, error, kind } => {
+                    self.status_log.push(format!(
+                        "✗ [{}] Failed to send to {} ({}): {}",
+                        index + 1,
+                        email,
+                        kind.label(),
+                        error
+                    ));
+                    self.record_status(
+                        index,
+                        &email,
+                        SendStatus::Failed { at: now_secs(), error },
+                    );
+                }
+                SendProgress::Recorded { index, summary } => {
+                    self.status_log.push(format!("— [{}] {}", index + 1, summary));
+                }
+                SendProgress::Retrying { index, email, error, attempt, delay } => {
+                    self.status_log.push(format!(
+                        "↻ [{}] Temporary failure for {}, retry {} in {:.1}s: {}",
+                        index + 1,
+                        email,
+                        attempt,
+                        delay.as_secs_f32(),
+                        error
+                    ));
+                }
+                SendProgress::Paused => {
+                    self.status_log.push("— Bulk send paused.".to_string());
+                    self.is_paused = true;
+                }
+                SendProgress::Resumed => {
+                    self.status_log.push("— Bulk send resumed.".to_string());
+                    self.is_paused = false;
+                }
+                SendProgress::Cancelled { remaining } => {
+                    self.status_log.push(format!(
+                        "— Bulk send cancelled; {} recipient(s) not sent.",
+                        remaining
+                    ));
+                    self.finish_sending();
+                    return;
+                }
+                SendProgress::Done => {
+                    if self.send_job == SendJob::Bulk {
                         self.status_log.push("— Bulk send complete.".to_string());
-                        self.is_sending = false;
-                        self.progress_rx = None;
-                        return;
+                    }
+                    self.finish_sending();
+                    return;
+                }
+                SendProgress::Written => {
+                    if let SendJob::DryRun { written } = &mut self.send_job {
+                        *written += 1;
                     }
                 }
+                SendProgress::DryRunDone { out_dir, result } => {
+                    self.finish_dry_run(&out_dir, result);
+                    self.finish_sending();
+                    return;
+                }
             }
         }
     }
 }
 
 impl eframe::App for EmailApp {
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if self.unsaved_since.is_some() {
+            self.save_templates();
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if self.setup.is_some() {
+            self.show_setup(ctx);
+            return;
+        }
+        if self.recovery.is_some() {
+            self.show_recovery(ctx);
+            return;
+        }
+
         self.poll_progress();
+        self.poll_sign_in();
+        self.save_when_idle(ctx);
+
+        if let Some(editor) = &mut self.settings_editor {
+            editor.poll_test();
+        }
 
         // Request repaint while sending so we see progress updates
-        if self.is_sending {
+        let testing = self.settings_editor.as_ref().is_some_and(|e| e.test_rx.is_some());
+        if self.is_sending || self.sign_in_rx.is_some() || testing {
             ctx.request_repaint();
         }
 
         // --- Confirmation Dialog ---
-        if self.show_confirm_dialog {
+        // The profile can go away while the dialog is open
+        if self.show_confirm_dialog
+            && let Some(idx) = self.selected_template
+            && let Err(e) = self.config_for(&self.templates[idx])
+        {
+            self.status_log.push(format!("✗ Bulk send not started: {}", e));
+            self.show_confirm_dialog = false;
+            self.confirm_report = None;
+        }
+        if self.show_confirm_dialog
+            && let Some(idx) = self.selected_template
+            && let Ok(config) = self.settings.profile(self.templates[idx].sender_profile.as_deref())
+        {
+            let template = &self.templates[idx];
+            let targets: Vec<usize> = if self.confirm_unsent_only {
+                template.unsent_recipients()
+            } else {
+                (0..template.recipients.len()).collect()
+            };
+            let report = self
+                .confirm_report
+                .get_or_insert_with(|| validate(config, template, &targets))
+                .clone();
+            let valid = report.valid_subset(&targets);
+            let already_sent = targets
+                .iter()
+                .filter(|&&i| template.recipients[i].status.is_sent())
+                .count();
+            let mut start: Option<Vec<usize>> = None;
+            let mut close = false;
+
             egui::Window::new("Confirm Bulk Send")
                 .collapsible(false)
                 .resizable(false)
                 .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
                 .show(ctx, |ui| {
-                    if let Some(idx) = self.selected_template {
-                        let count = self.templates[idx].recipients.len();
-                        ui.label(format!(
-                            "You are about to send emails to {} recipient(s).",
-                            count
-                        ));
-                        ui.label("Are you sure you want to proceed?");
-                        ui.add_space(10.0);
-                        ui.horizontal(|ui| {
-                            if ui.button("  Cancel  ").clicked() {
-                                self.show_confirm_dialog = false;
-                            }
-                            if ui
-                                .button(
-                                    egui::RichText::new("  Send All  ")
-                                        .color(egui::Color32::WHITE),
+                    ui.label(format!(
+                        "You are about to send emails to {} recipient(s).",
+                        targets.len()
+                    ));
+                    if already_sent > 0 {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(220, 160, 60),
+                            format!(
+                                "{} of them already received this email and will get it again.",
+                                already_sent
+                            ),
+                        );
+                    }
+
+                    if !report.is_clean() {
+                        ui.add_space(5.0);
+                        ui.colored_label(
+                            egui::Color32::from_rgb(220, 80, 80),
+                            "Validation found problems:",
+                        );
+                        egui::ScrollArea::vertical()
+                            .max_height(200.0)
+                            .show(ui, |ui| {
+                                for issue in &report.template_issues {
+                                    ui.label(format!("⚠ {}", issue));
+                                }
+                                for (i, issue) in &report.recipient_issues {
+                                    ui.label(format!("⚠ [{}] {}", i + 1, issue));
+                                }
+                            });
+                    }
+
+                    ui.label("Are you sure you want to proceed?");
+                    ui.add_space(10.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("  Cancel  ").clicked() {
+                            close = true;
+                        }
+                        if !report.is_clean()
+                            && ui
+                                .add_enabled(
+                                    !valid.is_empty(),
+                                    egui::Button::new(format!(
+                                        "  Send to {} Valid Only  ",
+                                        valid.len()
+                                    )),
                                 )
                                 .clicked()
-                            {
-                                self.show_confirm_dialog = false;
-                                // Start bulk send
-                                let (tx, rx) = mpsc::channel();
-                                let template = self.templates[idx].clone();
-                                let config = self.config.clone();
-                                self.progress_rx = Some(rx);
-                                self.is_sending = true;
-                                self.status_log
-                                    .push(format!("— Starting bulk send for '{}'...", template.name));
-                                send_bulk(config, template, tx);
+                        {
+                            start = Some(valid.clone());
+                        }
+                        let confirm_label = match (self.confirm_unsent_only, report.is_clean()) {
+                            (true, true) => "  Send Remaining  ",
+                            (false, true) => "  Send All  ",
+                            (true, false) => "  Send Remaining Anyway  ",
+                            (false, false) => "  Send All Anyway  ",
+                        };
+                        if ui
+                            .button(
+                                egui::RichText::new(confirm_label)
+                                    .color(egui::Color32::WHITE),
+                            )
+                            .clicked()
+                        {
+                            start = Some(targets.clone());
+                        }
+                    });
+                });
+
+            if let Some(targets) = start {
+                self.start_bulk_send(idx, targets);
+                close = true;
+            }
+            if close {
+                self.show_confirm_dialog = false;
+                self.confirm_report = None;
+            }
+        }
+
+        // --- CSV Import Mapping Dialog ---
+        let mut import_action: Option<bool> = None;
+        if let Some(import) = &mut self.csv_import {
+            egui::Window::new(format!("Import {}", import.file_name))
+                .collapsible(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "{} row(s) read, {} malformed.",
+                        import.table.rows.len(),
+                        import.table.errors.len()
+                    ));
+                    ui.label("Choose the column for each field:");
+                    ui.add_space(5.0);
+                    let headers = &import.table.headers;
+                    egui::Grid::new("csv_mapping_grid").show(ui, |ui| {
+                        ui.label("Email:");
+                        column_picker(ui, "csv_map_email", headers, &mut import.mapping.email);
+                        ui.end_row();
+                        ui.label("Display name:");
+                        column_picker(ui, "csv_map_display_name", headers, &mut import.mapping.display_name);
+                        ui.end_row();
+                        ui.label("Cc:");
+                        column_picker(ui, "csv_map_cc", headers, &mut import.mapping.cc);
+                        ui.end_row();
+                        ui.label("Bcc:");
+                        column_picker(ui, "csv_map_bcc", headers, &mut import.mapping.bcc);
+                        ui.end_row();
+                        for (key, col) in &mut import.mapping.args {
+                            ui.label(format!("{{{}}}:", key));
+                            column_picker(ui, format!("csv_map_{}", key), headers, col);
+                            ui.end_row();
+                        }
+                    });
+                    ui.checkbox(
+                        &mut import.mapping.include_unmapped,
+                        "Also import other columns as extra fields",
+                    );
+                    ui.add_space(10.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("  Cancel  ").clicked() {
+                            import_action = Some(false);
+                        }
+                        if ui
+                            .add_enabled(
+                                import.mapping.email.is_some() && !self.is_sending,
+                                egui::Button::new("  Import  "),
+                            )
+                            .clicked()
+                        {
+                            import_action = Some(true);
+                        }
+                    });
+                });
+        }
+        match import_action {
+            Some(true) => {
+                if let Some(import) = self.csv_import.take() {
+                    self.finish_csv_import(import);
+                }
+            }
+            Some(false) => self.csv_import = None,
+            None => {}
+        }
+
+        // --- Link URL Prompt ---
+        let mut link_action: Option<bool> = None;
+        if let Some(prompt) = &mut self.link_prompt {
+            egui::Window::new("Insert Link")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.label("URL:");
+                    let url_edit = ui.add(
+                        egui::TextEdit::singleline(&mut prompt.url).desired_width(320.0),
+                    );
+                    url_edit.request_focus();
+                    let entered = url_edit.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    ui.add_space(10.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("  Cancel  ").clicked() {
+                            link_action = Some(false);
+                        }
+                        let valid = !prompt.url.trim().is_empty();
+                        if ui.add_enabled(valid, egui::Button::new("  Insert  ")).clicked()
+                            || (entered && valid)
+                        {
+                            link_action = Some(true);
+                        }
+                    });
+                });
+        }
+        match link_action {
+            Some(true) => {
+                if let Some(prompt) = self.link_prompt.take()
+                    && let Some(t) = self.templates.iter_mut().find(|t| t.id == prompt.template_id)
+                {
+                    let id = body_editor_id(&t.id);
+                    let range = richtext::insert_link(&mut t.body, prompt.selection, prompt.url.trim(), t.body_format);
+                    let mut state = egui::text_edit::TextEditState::load(ctx, id).unwrap_or_default();
+                    set_body_selection(ctx, &mut state, id, range);
+                    self.save_templates();
+                }
+            }
+            Some(false) => self.link_prompt = None,
+            None => {}
+        }
+
+        // --- Settings Window ---
+        let mut settings_action: Option<bool> = None;
+        if let Some(editor) = &mut self.settings_editor {
+            let mut select = None;
+            let mut add = false;
+            let mut remove = false;
+            let mut test = false;
+            egui::Window::new("Settings")
+                .collapsible(false)
+                .default_width(640.0)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    ui.horizontal_top(|ui| {
+                        ui.vertical(|ui| {
+                            ui.set_width(150.0);
+                            ui.label(egui::RichText::new("Profiles").strong());
+                            for (i, form) in editor.forms.iter().enumerate() {
+                                let name = if form.name.trim().is_empty() {
+                                    "(unnamed)"
+                                } else {
+                                    form.name.as_str()
+                                };
+                                if ui.selectable_label(i == editor.selected, name).clicked() {
+                                    select = Some(i);
+                                }
                             }
+                            ui.add_space(5.0);
+                            ui.horizontal(|ui| {
+                                if ui.button("+ Add").clicked() {
+                                    add = true;
+                                }
+                                if ui
+                                    .add_enabled(editor.forms.len() > 1, egui::Button::new("🗑 Remove"))
+                                    .clicked()
+                                {
+                                    remove = true;
+                                }
+                            });
+                        });
+                        ui.separator();
+                        ui.vertical(|ui| {
+                            egui::ScrollArea::vertical()
+                                .max_height(420.0)
+                                .show(ui, |ui| {
+                                    let form = &mut editor.forms[editor.selected];
+                                    form.show(ui);
+                                    if let Some(error) = &form.error {
+                                        ui.colored_label(egui::Color32::from_rgb(220, 80, 80), error);
+                                    }
+                                });
                         });
+                    });
+
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(editor.test_rx.is_none(), egui::Button::new("🔌 Test Connection"))
+                            .on_hover_text("Connect and log in with the values above, without sending anything")
+                            .clicked()
+                        {
+                            test = true;
+                        }
+                        if editor.test_rx.is_some() {
+                            ui.spinner();
+                        }
+                    });
+                    for step in &editor.test_steps {
+                        match &step.result {
+                            Ok(detail) => ui.colored_label(
+                                egui::Color32::from_rgb(80, 200, 80),
+                                format!("✓ {}: {}", step.stage, detail),
+                            ),
+                            Err(e) => ui.colored_label(
+                                egui::Color32::from_rgb(220, 80, 80),
+                                format!("✗ {} failed: {}", step.stage, e),
+                            ),
+                        };
                     }
+
+                    ui.add_space(10.0);
+                    ui.label(
+                        egui::RichText::new(format!(
+                            "Saved to {}, rewriting the file: comments in it are not kept. A send already running keeps its old settings.",
+                            self.paths.config_file.display()
+                        ))
+                        .color(egui::Color32::from_rgb(150, 150, 150)),
+                    );
+                    if let Some(error) = &editor.error {
+                        ui.colored_label(egui::Color32::from_rgb(220, 80, 80), error);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("  Cancel  ").clicked() {
+                            settings_action = Some(false);
+                        }
+                        if ui.button("  Save  ").clicked() {
+                            settings_action = Some(true);
+                        }
+                    });
+                });
+
+            if add {
+                editor.forms.push(ProfileForm {
+                    name: format!("Profile {}", editor.forms.len() + 1),
+                    ..ProfileForm::default()
                 });
+                select = Some(editor.forms.len() - 1);
+            }
+            if remove {
+                editor.forms.remove(editor.selected);
+                select = Some(editor.selected.min(editor.forms.len() - 1));
+            }
+            if let Some(i) = select {
+                if i != editor.selected || remove {
+                    editor.test_rx = None;
+                    editor.test_steps.clear();
+                }
+                editor.selected = i;
+            }
+            if test {
+                editor.start_test();
+            }
+        }
+        match settings_action {
+            Some(true) => self.save_settings_editor(),
+            Some(false) => self.settings_editor = None,
+            None => {}
         }
 
         // --- Left Panel: Template List ---
         egui::SidePanel::left("template_list")
             .min_width(200.0)
             .show(ctx, |ui| {
-                ui.heading("Templates");
+                ui.horizontal(|ui| {
+                    ui.heading("Templates");
+                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if ui.button("⚙ Settings").clicked() && self.settings_editor.is_none() {
+                            self.settings_editor = Some(SettingsEditor::new(&self.settings));
+                        }
+                    });
+                });
+                if let Some(error) = &self.save_error {
+                    ui.colored_label(egui::Color32::from_rgb(220, 80, 80), "⚠ Changes are not being saved")
+                        .on_hover_text(error);
+                }
                 ui.separator();
 
                 ui.horizontal(|ui| {
@@ -157,6 +1506,9 @@ impl eframe::App for EmailApp {
                         if ui.selectable_label(Some(i) == self.selected_template, label).clicked() {
                             self.selected_template = Some(i);
                             self.new_recipient_email.clear();
+                            self.new_recipient_name.clear();
+                            self.new_recipient_cc.clear();
+                            self.new_recipient_bcc.clear();
                             self.new_recipient_args.clear();
                             self.preview_recipient_idx = None;
                         }
@@ -170,10 +1522,7 @@ impl eframe::App for EmailApp {
                 }
 
                 if let Some(clone_idx) = to_clone {
-                    let source = &self.templates[clone_idx];
-                    let mut cloned = source.clone();
-                    cloned.id = uuid::Uuid::new_v4().to_string();
-                    cloned.name = format!("{} - copy", source.name);
+                    let cloned = self.templates[clone_idx].duplicate();
                     self.templates.push(cloned);
                     self.selected_template = Some(self.templates.len() - 1);
                     self.save_templates();
@@ -183,10 +1532,10 @@ impl eframe::App for EmailApp {
                     self.templates.remove(del);
                     if self.selected_template == Some(del) {
                         self.selected_template = None;
-                    } else if let Some(sel) = self.selected_template {
-                        if sel > del {
-                            self.selected_template = Some(sel - 1);
-                        }
+                    } else if let Some(sel) = self.selected_template
+                        && sel > del
+                    {
+                        self.selected_template = Some(sel - 1);
                     }
                     self.save_templates();
                 }
@@ -206,6 +1555,8 @@ impl eframe::App for EmailApp {
                                 ui.colored_label(egui::Color32::from_rgb(80, 200, 80), line);
                             } else if line.starts_with('✗') {
                                 ui.colored_label(egui::Color32::from_rgb(220, 80, 80), line);
+                            } else if line.starts_with('↻') {
+                                ui.colored_label(egui::Color32::from_rgb(220, 160, 60), line);
                             } else {
                                 ui.label(line);
                             }
@@ -219,6 +1570,8 @@ impl eframe::App for EmailApp {
                 // We need to work with a clone to avoid borrow issues, then copy back
                 let mut template = self.templates[idx].clone();
                 let mut changed = false;
+                // Nothing can be sent while the template names a missing profile
+                let profile_ok = self.config_for(&template).is_ok();
 
                 ui.heading(format!("Editing: {}", template.name));
                 ui.separator();
@@ -234,6 +1587,166 @@ impl eframe::App for EmailApp {
 
                     ui.add_space(5.0);
 
+                    // --- Sender Profile ---
+                    let mut sign_in = None;
+                    ui.horizontal(|ui| {
+                        ui.label("Send from:");
+                        let current = self.settings.profile(template.sender_profile.as_deref());
+                        let selected_text = match current {
+                            Ok(current) => format!("{} <{}>", current.name, current.sender_address()),
+                            Err(_) => format!("{} (missing)", template.sender_profile.as_deref().unwrap_or("")),
+                        };
+                        egui::ComboBox::from_id_salt("sender_profile")
+                            .selected_text(selected_text)
+                            .show_ui(ui, |ui| {
+                                for profile in &self.settings.profiles {
+                                    let selected = current.as_ref().is_ok_and(|c| c.name == profile.name);
+                                    if ui
+                                        .selectable_label(
+                                            selected,
+                                            format!("{} <{}>", profile.name, profile.sender_address()),
+                                        )
+                                        .clicked()
+                                        && !selected
+                                    {
+                                        template.sender_profile = Some(profile.name.clone());
+                                        changed = true;
+                                    }
+                                }
+                            });
+
+                        let current = match current {
+                            Ok(current) => current,
+                            Err(e) => {
+                                ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("⚠ {}", e));
+                                return;
+                            }
+                        };
+                        if current.oauth2.is_some() {
+                            let signed_in = *self
+                                .signed_in
+                                .entry(current.name.clone())
+                                .or_insert_with(|| oauth::is_signed_in(current));
+                            if signed_in {
+                                ui.label("🔑 Signed in");
+                            } else {
+                                ui.colored_label(egui::Color32::from_rgb(220, 80, 80), "Not signed in");
+                            }
+                            let label = if signed_in { "Sign in again" } else { "Sign in..." };
+                            if ui
+                                .add_enabled(self.sign_in_rx.is_none(), egui::Button::new(label))
+                                .clicked()
+                            {
+                                sign_in = Some(current.clone());
+                            }
+                        }
+                    });
+                    if let Some(config) = sign_in.take() {
+                        self.start_sign_in(config);
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Always Cc:");
+                        if ui
+                            .add(
+                                egui::TextEdit::singleline(&mut template.cc)
+                                    .desired_width(250.0)
+                                    .hint_text("archive@crm.example.com"),
+                            )
+                            .changed()
+                        {
+                            changed = true;
+                        }
+                        ui.label("Always Bcc:");
+                        if ui
+                            .add(egui::TextEdit::singleline(&mut template.bcc).desired_width(250.0))
+                            .changed()
+                        {
+                            changed = true;
+                        }
+                    });
+
+                    // --- Headers & Unsubscribe ---
+                    egui::CollapsingHeader::new("Headers & Unsubscribe")
+                        .id_salt("headers_section")
+                        .default_open(!template.headers.is_empty() || !template.unsubscribe.url.is_empty())
+                        .show(ui, |ui| {
+                            ui.label("Bulk senders should offer an unsubscribe link; placeholders give each recipient their own.");
+                            egui::Grid::new("unsubscribe_grid").num_columns(2).show(ui, |ui| {
+                                ui.label("Unsubscribe URL:");
+                                if ui
+                                    .add(
+                                        egui::TextEdit::singleline(&mut template.unsubscribe.url)
+                                            .desired_width(400.0)
+                                            .hint_text("https://example.com/unsubscribe?id={customer_id}"),
+                                    )
+                                    .changed()
+                                {
+                                    changed = true;
+                                }
+                                ui.end_row();
+                                ui.label("Unsubscribe mailto:");
+                                if ui
+                                    .add(
+                                        egui::TextEdit::singleline(&mut template.unsubscribe.mailto)
+                                            .desired_width(400.0)
+                                            .hint_text("unsubscribe@example.com?subject=unsubscribe-{customer_id}"),
+                                    )
+                                    .changed()
+                                {
+                                    changed = true;
+                                }
+                                ui.end_row();
+                            });
+                            if ui
+                                .checkbox(&mut template.unsubscribe.one_click, "One-click unsubscribe (List-Unsubscribe-Post)")
+                                .on_hover_text("Mailbox providers POST to the URL to unsubscribe without opening it; the URL must use https")
+                                .changed()
+                            {
+                                changed = true;
+                            }
+
+                            ui.add_space(5.0);
+                            ui.label("Custom headers (values may use placeholders):");
+                            let mut header_to_remove: Option<usize> = None;
+                            for (hi, header) in template.headers.iter_mut().enumerate() {
+                                ui.horizontal(|ui| {
+                                    if ui
+                                        .add(
+                                            egui::TextEdit::singleline(&mut header.name)
+                                                .desired_width(160.0)
+                                                .hint_text("X-Campaign"),
+                                        )
+                                        .changed()
+                                    {
+                                        changed = true;
+                                    }
+                                    ui.label(":");
+                                    if ui
+                                        .add(egui::TextEdit::singleline(&mut header.value).desired_width(300.0))
+                                        .changed()
+                                    {
+                                        changed = true;
+                                    }
+                                    if ui.small_button("Remove").clicked() {
+                                        header_to_remove = Some(hi);
+                                    }
+                                });
+                            }
+                            if let Some(rm) = header_to_remove {
+                                template.headers.remove(rm);
+                                changed = true;
+                            }
+                            if ui.button("+ Add Header").clicked() {
+                                template.headers.push(CustomHeader::default());
+                                changed = true;
+                            }
+                            for issue in template.header_issues() {
+                                ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("⚠ {}", issue));
+                            }
+                        });
+
+                    ui.add_space(5.0);
+
                     // --- Subject ---
                     ui.horizontal(|ui| {
                         ui.label("Subject:");
@@ -245,79 +1758,143 @@ impl eframe::App for EmailApp {
                     ui.add_space(5.0);
 
                     // --- Body ---
-                    ui.label("Body (use {placeholder} for per-recipient variables):");
-                    ui.label("Tip: Use the toolbar below to format text, or type HTML tags directly (e.g. <b>bold</b>).");
+                    ui.label("Body (use {placeholder} for per-recipient variables, {name|fallback} for defaults, {#if key}…{/if} and {#each list}…{.}…{/each} for blocks):");
+                    ui.horizontal(|ui| {
+                        ui.label("Format:");
+                        for format in [BodyFormat::Html, BodyFormat::Markdown] {
+                            if ui
+                                .radio_value(&mut template.body_format, format, format.label())
+                                .changed()
+                            {
+                                changed = true;
+                            }
+                        }
+                    });
+                    let markdown = template.body_format == BodyFormat::Markdown;
+                    if markdown {
+                        ui.label("Tip: Use the toolbar below to format text, or write Markdown directly (e.g. **bold**, [link](https://example.com), - list item).");
+                    } else {
+                        ui.label("Tip: Use the toolbar below to format text, or type HTML tags directly (e.g. <b>bold</b>).");
+                    }
+                    let (bold, italic) = if markdown {
+                        (("**", "**"), ("*", "*"))
+                    } else {
+                        (("<b>", "</b>"), ("<i>", "</i>"))
+                    };
+                    // Markdown has no underline syntax; inline HTML is allowed there.
+                    let underline = ("<u>", "</u>");
                     ui.add_space(3.0);
 
                     // --- Formatting Toolbar ---
+                    // Toolbar actions apply to the editor's selection once it has been laid out below.
+                    let mut body_action: Option<BodyAction> = None;
                     ui.horizontal(|ui| {
                         if ui.button(egui::RichText::new(" B ").strong().size(14.0))
-                            .on_hover_text("Bold (wraps selection in <b></b>)")
+                            .on_hover_text("Bold (Ctrl+B)")
                             .clicked()
                         {
-                            wrap_body_selection(&mut template.body, "<b>", "</b>");
-                            changed = true;
+                            body_action = Some(BodyAction::Wrap(bold.0, bold.1));
                         }
                         if ui.button(egui::RichText::new(" I ").italics().size(14.0))
-                            .on_hover_text("Italic (wraps selection in <i></i>)")
+                            .on_hover_text("Italic (Ctrl+I)")
                             .clicked()
                         {
-                            wrap_body_selection(&mut template.body, "<i>", "</i>");
-                            changed = true;
+                            body_action = Some(BodyAction::Wrap(italic.0, italic.1));
                         }
                         if ui.button(egui::RichText::new(" U ").underline().size(14.0))
-                            .on_hover_text("Underline (wraps selection in <u></u>)")
+                            .on_hover_text("Underline (Ctrl+U)")
                             .clicked()
                         {
-                            wrap_body_selection(&mut template.body, "<u>", "</u>");
-                            changed = true;
+                            body_action = Some(BodyAction::Wrap(underline.0, underline.1));
                         }
                         ui.separator();
                         if ui.button("Link")
-                            .on_hover_text("Insert a hyperlink at cursor")
+                            .on_hover_text("Link the selected text, or insert a link at the cursor")
                             .clicked()
                         {
-                            template.body.push_str("<a href=\"URL\">link text</a>");
-                            changed = true;
+                            body_action = Some(BodyAction::Link);
                         }
-                        if ui.button("• List Item")
-                            .on_hover_text("Insert a bullet point")
+                        if ui.button("• List")
+                            .on_hover_text("Turn the selected lines into a bulleted list (or back)")
                             .clicked()
                         {
-                            template.body.push_str("\n• ");
-                            changed = true;
+                            body_action = Some(BodyAction::List);
                         }
                     });
 
                     ui.add_space(3.0);
 
-                    let body_edit = ui
-                        .add(
-                            egui::TextEdit::multiline(&mut template.body)
-                                .desired_width(f32::INFINITY)
-                                .desired_rows(8)
-                                .hint_text("Hello {name},\n\nI wanted to reach out about..."),
-                        );
-                    if body_edit.changed() {
+                    let body_id = body_editor_id(&template.id);
+                    let mut body_output = egui::TextEdit::multiline(&mut template.body)
+                        .id(body_id)
+                        .desired_width(f32::INFINITY)
+                        .desired_rows(8)
+                        .hint_text("Hello {name},\n\nI wanted to reach out about...")
+                        .show(ui);
+                    if body_output.response.changed() {
                         changed = true;
                     }
 
                     // Handle keyboard shortcuts for formatting (Ctrl+B, Ctrl+I, Ctrl+U)
-                    if body_edit.has_focus() {
+                    if body_output.response.has_focus() {
                         let modifiers = ui.input(|i| i.modifiers);
                         if modifiers.ctrl || modifiers.command {
                             if ui.input(|i| i.key_pressed(egui::Key::B)) {
-                                wrap_body_selection(&mut template.body, "<b>", "</b>");
-                                changed = true;
+                                body_action = Some(BodyAction::Wrap(bold.0, bold.1));
                             }
                             if ui.input(|i| i.key_pressed(egui::Key::I)) {
-                                wrap_body_selection(&mut template.body, "<i>", "</i>");
-                                changed = true;
+                                body_action = Some(BodyAction::Wrap(italic.0, italic.1));
                             }
                             if ui.input(|i| i.key_pressed(egui::Key::U)) {
-                                wrap_body_selection(&mut template.body, "<u>", "</u>");
-                                changed = true;
+                                body_action = Some(BodyAction::Wrap(underline.0, underline.1));
+                            }
+                        }
+                    }
+
+                    if let Some(action) = body_action {
+                        let selection = body_selection(&body_output.state, &template.body);
+                        let new_selection = match action {
+                            BodyAction::Wrap(open, close) => {
+                                Some(richtext::toggle_wrap(&mut template.body, selection, open, close))
+                            }
+                            BodyAction::List => {
+                                Some(richtext::toggle_list(&mut template.body, selection, template.body_format))
                             }
+                            BodyAction::Link => {
+                                self.link_prompt = Some(LinkPrompt {
+                                    template_id: template.id.clone(),
+                                    selection,
+                                    url: "https://".to_string(),
+                                });
+                                None
+                            }
+                        };
+                        if let Some(range) = new_selection {
+                            set_body_selection(ui.ctx(), &mut body_output.state, body_id, range);
+                            changed = true;
+                        }
+                    }
+
+                    // --- Plain-text version ---
+                    ui.add_space(3.0);
+                    let mut manual_plain = template.plain_body.is_some();
+                    if ui
+                        .checkbox(&mut manual_plain, "Write plain-text version manually")
+                        .on_hover_text("By default the text/plain part is generated from the HTML body")
+                        .changed()
+                    {
+                        template.plain_body = manual_plain.then(|| html_to_text(&template.body_to_html(&template.body)));
+                        changed = true;
+                    }
+                    if let Some(plain) = &mut template.plain_body {
+                        let plain_edit = ui.add(
+                            egui::TextEdit::multiline(plain)
+                                .desired_width(f32::INFINITY)
+                                .desired_rows(6)
+                                .hint_text("Plain-text version of the email"),
+                        );
+                        if plain_edit.changed() {
+                            changed = true;
                         }
                     }
 
@@ -332,6 +1909,9 @@ impl eframe::App for EmailApp {
                             }
                         });
                     }
+                    for error in template.syntax_errors() {
+                        ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("⚠ {}", error));
+                    }
 
                     ui.add_space(10.0);
                     ui.separator();
@@ -347,6 +1927,23 @@ impl eframe::App for EmailApp {
                                     .map(|n| n.to_string_lossy().to_string())
                                     .unwrap_or_else(|| path.to_string_lossy().to_string())
                             ));
+                            if path.to_string_lossy().contains('{') {
+                                ui.colored_label(egui::Color32::from_rgb(150, 150, 150), "per recipient")
+                                    .on_hover_text(path.display().to_string());
+                            } else {
+                                match std::fs::metadata(path) {
+                                    Ok(meta) => {
+                                        ui.colored_label(
+                                            egui::Color32::from_rgb(150, 150, 150),
+                                            format_size(meta.len()),
+                                        );
+                                    }
+                                    Err(_) => {
+                                        ui.colored_label(egui::Color32::from_rgb(220, 80, 80), "⚠ not found")
+                                            .on_hover_text(path.display().to_string());
+                                    }
+                                }
+                            }
                             if ui.small_button("Remove").clicked() {
                                 attachment_to_remove = Some(ai);
                             }
@@ -357,13 +1954,120 @@ impl eframe::App for EmailApp {
                         changed = true;
                     }
 
-                    if ui.button("📁 Add Attachment(s)").clicked() {
-                        if let Some(files) = rfd::FileDialog::new().pick_files() {
-                            for f in files {
-                                template.attachment_paths.push(f);
-                            }
+                    if ui.button("📁 Add Attachment(s)").clicked()
+                        && let Some(files) = rfd::FileDialog::new().pick_files()
+                    {
+                        for f in files {
+                            template.attachment_paths.push(f);
+                        }
+                        changed = true;
+                    }
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.new_attachment_pattern)
+                                .desired_width(300.0)
+                                .hint_text("invoices/{customer_id}.pdf"),
+                        )
+                        .on_hover_text("A path with placeholders, filled in for each recipient");
+                        if ui.button("+ Add Path").clicked() && !self.new_attachment_pattern.trim().is_empty() {
+                            template
+                                .attachment_paths
+                                .push(self.new_attachment_pattern.trim().into());
+                            self.new_attachment_pattern.clear();
                             changed = true;
                         }
+                    });
+
+                    ui.add_space(10.0);
+                    ui.separator();
+
+                    // --- Inline Images ---
+                    ui.heading("Inline Images");
+                    ui.label("Images embedded in the email and shown in the body where they are referenced (e.g. <img src=\"cid:logo\">).");
+                    let mut image_to_insert: Option<usize> = None;
+                    let mut image_to_remove: Option<usize> = None;
+                    for (ii, image) in template.inline_images.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.label("🖼");
+                            ui.code(format!("cid:{}", image.cid));
+                            ui.label(
+                                image.path.file_name()
+                                    .map(|n| n.to_string_lossy().to_string())
+                                    .unwrap_or_else(|| image.path.to_string_lossy().to_string()),
+                            );
+                            if ui.small_button("Insert").on_hover_text("Insert at the body cursor").clicked() {
+                                image_to_insert = Some(ii);
+                            }
+                            if ui.small_button("Remove").clicked() {
+                                image_to_remove = Some(ii);
+                            }
+                        });
+                    }
+
+                    if ui.button("🖼 Add Inline Image").clicked()
+                        && let Some(path) = rfd::FileDialog::new()
+                            .add_filter("Images", &["png", "jpg", "jpeg", "gif", "webp", "svg"])
+                            .pick_file()
+                    {
+                        template.add_inline_image(path);
+                        image_to_insert = Some(template.inline_images.len() - 1);
+                    }
+                    if let Some(ii) = image_to_insert {
+                        let cid = template.inline_images[ii].cid.clone();
+                        let id = body_editor_id(&template.id);
+                        let mut state = egui::text_edit::TextEditState::load(ui.ctx(), id).unwrap_or_default();
+                        let selection = body_selection(&state, &template.body);
+                        let range = richtext::insert_image(&mut template.body, selection, &cid, &cid, template.body_format);
+                        set_body_selection(ui.ctx(), &mut state, id, range);
+                        changed = true;
+                    }
+                    if let Some(rm) = image_to_remove {
+                        template.inline_images.remove(rm);
+                        changed = true;
+                    }
+
+                    // Encoded size of everything that travels with one email, for the
+                    // previewed (or first) recipient since attachments can differ per recipient
+                    let size_recipient = self
+                        .preview_recipient_idx
+                        .and_then(|i| template.recipients.get(i))
+                        .or(template.recipients.first())
+                        .cloned()
+                        .unwrap_or_default();
+                    let encoded = estimated_attachments_size(&template, &size_recipient);
+                    if encoded > 0 {
+                        ui.add_space(5.0);
+                        let profile = self.config_for(&template).ok();
+                        let label = if size_recipient.email.is_empty() {
+                            "Attachments".to_string()
+                        } else {
+                            format!("Attachments for {}", size_recipient.email)
+                        };
+                        match profile.as_ref().and_then(|p| Some((p.attachment_limit()?, &p.name))) {
+                            Some((limit, profile)) if encoded > limit => {
+                                ui.colored_label(
+                                    egui::Color32::from_rgb(220, 80, 80),
+                                    format!(
+                                        "⚠ {} ≈ {} encoded, over the {} limit of profile '{}'",
+                                        label,
+                                        format_size(encoded),
+                                        format_size(limit),
+                                        profile
+                                    ),
+                                );
+                            }
+                            Some((limit, _)) => {
+                                ui.label(format!(
+                                    "{} ≈ {} encoded (limit {})",
+                                    label,
+                                    format_size(encoded),
+                                    format_size(limit)
+                                ));
+                            }
+                            None => {
+                                ui.label(format!("{} ≈ {} encoded", label, format_size(encoded)));
+                            }
+                        }
                     }
 
                     ui.add_space(10.0);
@@ -373,6 +2077,41 @@ impl eframe::App for EmailApp {
                     ui.heading("Recipients");
                     ui.add_space(5.0);
 
+                    // Statuses from a running send are saved by position, so the
+                    // list can't change under it
+                    let editable = !self.is_sending;
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(editable, egui::Button::new("📥 Import CSV"))
+                            .clicked()
+                            && let Some(path) = rfd::FileDialog::new()
+                                .add_filter("CSV", &["csv"])
+                                .pick_file()
+                        {
+                            self.start_csv_import(&template, &path);
+                        }
+                        if ui
+                            .add_enabled(!template.recipients.is_empty(), egui::Button::new("📤 Export CSV"))
+                            .clicked()
+                            && let Some(path) = rfd::FileDialog::new()
+                                .add_filter("CSV", &["csv"])
+                                .set_file_name(format!("{}.csv", template.name))
+                                .save_file()
+                        {
+                            match contacts::export_csv(&path, &template) {
+                                Ok(()) => self.status_log.push(format!(
+                                    "— Exported {} recipient(s) to {}",
+                                    template.recipients.len(),
+                                    path.display()
+                                )),
+                                Err(e) => self
+                                    .status_log
+                                    .push(format!("✗ Export to {} failed: {}", path.display(), e)),
+                            }
+                        }
+                    });
+                    ui.add_space(5.0);
+
                     let placeholders = template.extract_placeholders();
 
                     // Table of current recipients
@@ -386,10 +2125,15 @@ impl eframe::App for EmailApp {
                             .show(ui, |ui| {
                                 // Header
                                 ui.strong("#");
-                                ui.strong("Email");
+                                ui.strong("Email (To)");
+                                ui.strong("Name");
+                                ui.strong("Cc");
+                                ui.strong("Bcc");
                                 for p in &placeholders {
                                     ui.strong(p);
                                 }
+                                ui.strong("Files");
+                                ui.strong("Status");
                                 ui.strong("Actions");
                                 ui.end_row();
 
@@ -397,11 +2141,27 @@ impl eframe::App for EmailApp {
                                     ui.label(format!("{}", ri + 1));
 
                                     if ui
-                                        .add(egui::TextEdit::singleline(&mut recipient.email).desired_width(200.0))
+                                        .add_enabled(
+                                            editable,
+                                            egui::TextEdit::singleline(&mut recipient.email).desired_width(200.0),
+                                        )
+                                        .on_hover_text("Separate several To addresses with commas")
                                         .changed()
                                     {
                                         changed = true;
                                     }
+                                    for (field, width) in [
+                                        (&mut recipient.display_name, 120.0),
+                                        (&mut recipient.cc, 150.0),
+                                        (&mut recipient.bcc, 150.0),
+                                    ] {
+                                        if ui
+                                            .add_enabled(editable, egui::TextEdit::singleline(field).desired_width(width))
+                                            .changed()
+                                        {
+                                            changed = true;
+                                        }
+                                    }
 
                                     for p in &placeholders {
                                         let val = recipient
@@ -409,7 +2169,7 @@ impl eframe::App for EmailApp {
                                             .entry(p.clone())
                                             .or_default();
                                         if ui
-                                            .add(egui::TextEdit::singleline(val).desired_width(120.0))
+                                            .add_enabled(editable, egui::TextEdit::singleline(val).desired_width(120.0))
                                             .changed()
                                         {
                                             changed = true;
@@ -417,10 +2177,55 @@ impl eframe::App for EmailApp {
                                     }
 
                                     ui.horizontal(|ui| {
-                                        if ui.small_button("Send").clicked() {
+                                        let files = &recipient.attachments;
+                                        let button = ui.add_enabled(
+                                            editable,
+                                            egui::Button::new(format!("📎 {}", files.len())).small(),
+                                        );
+                                        let button = if files.is_empty() {
+                                            button.on_hover_text("Attach files for this recipient only")
+                                        } else {
+                                            button.on_hover_text(
+                                                files
+                                                    .iter()
+                                                    .map(|p| p.display().to_string())
+                                                    .collect::<Vec<_>>()
+                                                    .join("\n"),
+                                            )
+                                        };
+                                        if button.clicked()
+                                            && let Some(picked) = rfd::FileDialog::new().pick_files()
+                                        {
+                                            recipient.attachments.extend(picked);
+                                            changed = true;
+                                        }
+                                        if !recipient.attachments.is_empty()
+                                            && ui
+                                                .add_enabled(editable, egui::Button::new("✕").small())
+                                                .on_hover_text("Remove this recipient's files")
+                                                .clicked()
+                                        {
+                                            recipient.attachments.clear();
+                                            changed = true;
+                                        }
+                                    });
+
+                                    status_label(ui, &recipient.status);
+
+                                    ui.horizontal(|ui| {
+                                        if ui
+                                            .add_enabled(
+                                                profile_ok && !self.is_sending,
+                                                egui::Button::new("Send").small(),
+                                            )
+                                            .clicked()
+                                        {
                                             send_single_idx = Some(ri);
                                         }
-                                        if ui.small_button("🗑").clicked() {
+                                        if ui
+                                            .add_enabled(editable, egui::Button::new("🗑").small())
+                                            .clicked()
+                                        {
                                             recipient_to_remove = Some(ri);
                                         }
                                         if ui.small_button("👁").on_hover_text("Preview").clicked() {
@@ -439,21 +2244,9 @@ impl eframe::App for EmailApp {
                         changed = true;
                     }
 
-                    // Handle single send
+                    // Handle single send; the result arrives through poll_progress
                     if let Some(si) = send_single_idx {
-                        let recipient = template.recipients[si].clone();
-                        let config = self.config.clone();
-                        let tmpl = template.clone();
-                        match send_single(&config, &tmpl, &recipient) {
-                            Ok(()) => {
-                                self.status_log
-                                    .push(format!("✓ Sent to {}", recipient.email));
-                            }
-                            Err(e) => {
-                                self.status_log
-                                    .push(format!("✗ Failed to send to {}: {}", recipient.email, e));
-                            }
-                        }
+                        self.start_single_send(&template, si);
                     }
 
                     ui.add_space(5.0);
@@ -463,7 +2256,21 @@ impl eframe::App for EmailApp {
                         ui.label("Add Recipient:");
                         ui.horizontal(|ui| {
                             ui.label("Email:");
-                            ui.text_edit_singleline(&mut self.new_recipient_email);
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.new_recipient_email)
+                                    .hint_text("jane@example.com, john@example.com"),
+                            );
+                            ui.label("Name:");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.new_recipient_name)
+                                    .hint_text("Jane Doe"),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Cc:");
+                            ui.text_edit_singleline(&mut self.new_recipient_cc);
+                            ui.label("Bcc:");
+                            ui.text_edit_singleline(&mut self.new_recipient_bcc);
                         });
                         for p in &placeholders {
                             ui.horizontal(|ui| {
@@ -479,7 +2286,12 @@ impl eframe::App for EmailApp {
                         {
                             let recipient = Recipient {
                                 email: self.new_recipient_email.clone(),
+                                display_name: std::mem::take(&mut self.new_recipient_name),
+                                cc: std::mem::take(&mut self.new_recipient_cc),
+                                bcc: std::mem::take(&mut self.new_recipient_bcc),
                                 args: self.new_recipient_args.clone(),
+                                attachments: Vec::new(),
+                                status: SendStatus::Pending,
                             };
                             template.recipients.push(recipient);
                             self.new_recipient_email.clear();
@@ -489,37 +2301,69 @@ impl eframe::App for EmailApp {
                     });
 
                     // --- Preview ---
-                    if let Some(pi) = self.preview_recipient_idx {
-                        if pi < template.recipients.len() {
-                            ui.add_space(10.0);
-                            ui.separator();
-                            ui.heading("📨 Preview");
-                            let r = &template.recipients[pi];
-                            ui.label(format!("To: {}", r.email));
-                            ui.label(format!("Subject: {}", template.render_subject(r)));
-                            ui.add_space(5.0);
-                            ui.group(|ui| {
-                                let rendered = template.render_body(r);
-                                render_html_preview(ui, &rendered);
-                            });
-                            ui.add_space(3.0);
-                            ui.colored_label(
-                                egui::Color32::from_rgb(150, 150, 150),
-                                "(This is an approximate preview. The actual email may render slightly differently in Gmail.)"
-                            );
-                            if !template.attachment_paths.is_empty() {
-                                ui.label(format!(
-                                    "Attachments: {}",
-                                    template
-                                        .attachment_paths
-                                        .iter()
-                                        .filter_map(|p| p.file_name())
-                                        .map(|n| n.to_string_lossy().to_string())
-                                        .collect::<Vec<_>>()
-                                        .join(", ")
-                                ));
+                    if let Some(pi) = self.preview_recipient_idx
+                        && pi < template.recipients.len()
+                    {
+                        ui.add_space(10.0);
+                        ui.separator();
+                        ui.heading("📨 Preview");
+                        let r = &template.recipients[pi];
+                        let join = |list: &[Mailbox]| {
+                            list.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+                        };
+                        match r.to_mailboxes() {
+                            Ok(to) => ui.label(format!("To: {}", join(&to))),
+                            Err(e) => ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("To: {}", e)),
+                        };
+                        for (field, own, shared) in [("Cc", &r.cc, &template.cc), ("Bcc", &r.bcc, &template.bcc)] {
+                            match (parse_address_list(own), parse_address_list(shared)) {
+                                (Ok(mut list), Ok(shared)) => {
+                                    list.extend(shared);
+                                    if !list.is_empty() {
+                                        ui.label(format!("{}: {}", field, join(&list)));
+                                    }
+                                }
+                                (Err(e), _) | (_, Err(e)) => {
+                                    ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("{}: {}", field, e));
+                                }
+                            }
+                        }
+                        ui.label(format!("Subject: {}", template.render_subject(r)));
+                        match template.render_headers(r) {
+                            Ok(headers) => {
+                                for (name, value) in headers {
+                                    ui.colored_label(
+                                        egui::Color32::from_rgb(150, 150, 150),
+                                        format!("{}: {}", name, value),
+                                    );
+                                }
+                            }
+                            Err(e) => {
+                                ui.colored_label(egui::Color32::from_rgb(220, 80, 80), format!("Headers: {}", e));
                             }
                         }
+                        ui.add_space(5.0);
+                        ui.group(|ui| {
+                            let rendered = template.render_body_html(r);
+                            render_html_preview(ui, &rendered, &template.inline_images);
+                        });
+                        ui.add_space(3.0);
+                        ui.colored_label(
+                            egui::Color32::from_rgb(150, 150, 150),
+                            "(This is an approximate preview. The actual email may render slightly differently in Gmail.)"
+                        );
+                        let attachments = template.attachments_for(r);
+                        if !attachments.is_empty() {
+                            ui.label(format!(
+                                "Attachments: {}",
+                                attachments
+                                    .iter()
+                                    .filter_map(|p| p.file_name())
+                                    .map(|n| n.to_string_lossy().to_string())
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ));
+                        }
                     }
 
                     ui.add_space(15.0);
@@ -527,7 +2371,7 @@ impl eframe::App for EmailApp {
 
                     // --- Send Buttons ---
                     ui.horizontal(|ui| {
-                        let can_send = !template.recipients.is_empty() && !self.is_sending;
+                        let can_send = !template.recipients.is_empty() && !self.is_sending && profile_ok;
 
                         if ui
                             .add_enabled(
@@ -539,24 +2383,88 @@ impl eframe::App for EmailApp {
                             )
                             .clicked()
                         {
+                            self.confirm_unsent_only = false;
                             self.show_confirm_dialog = true;
                         }
 
-                        if self.is_sending {
+                        let unsent = template.unsent_recipients().len();
+                        if unsent > 0
+                            && unsent < template.recipients.len()
+                            && ui
+                                .add_enabled(
+                                    !self.is_sending && profile_ok,
+                                    egui::Button::new(
+                                        egui::RichText::new(format!("↻ Resume ({} unsent)", unsent))
+                                            .size(16.0),
+                                    ),
+                                )
+                                .on_hover_text("Send only to recipients that are pending or failed")
+                                .clicked()
+                        {
+                            self.confirm_unsent_only = true;
+                            self.show_confirm_dialog = true;
+                        }
+
+                        if ui
+                            .add_enabled(
+                                !template.recipients.is_empty() && !self.is_sending && profile_ok,
+                                egui::Button::new(egui::RichText::new("🧪 Dry Run").size(16.0)),
+                            )
+                            .on_hover_text("Render every message to .eml files and a manifest without sending")
+                            .clicked()
+                            && let Some(dir) = rfd::FileDialog::new().pick_folder()
+                        {
+                            self.run_dry_run(&template, &dir);
+                        }
+
+                        if self.is_sending && self.send_job != SendJob::Bulk {
                             ui.spinner();
-                            ui.label("Sending...");
+                            match self.send_job {
+                                SendJob::DryRun { written } => {
+                                    ui.label(format!("Writing dry run ({} written)...", written))
+                                }
+                                _ => ui.label("Sending..."),
+                            };
+                        } else if self.is_sending {
+                            if self.is_paused {
+                                ui.label("Paused");
+                                if ui.button("▶ Resume").clicked() {
+                                    self.send_control(SendControl::Resume);
+                                }
+                            } else {
+                                ui.spinner();
+                                ui.label("Sending...");
+                                if ui.button("⏸ Pause").clicked() {
+                                    self.send_control(SendControl::Pause);
+                                }
+                            }
+                            if ui.button("⏹ Cancel").clicked() {
+                                self.send_control(SendControl::Cancel);
+                            }
                         }
                     });
 
-                    if ui.button("Clear Log").clicked() {
-                        self.status_log.clear();
-                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Clear Log").clicked() {
+                            self.status_log.clear();
+                        }
+                        if ui
+                            .add_enabled(!self.is_sending, egui::Button::new("Reset Statuses"))
+                            .on_hover_text("Mark every recipient as pending again")
+                            .clicked()
+                        {
+                            for r in &mut template.recipients {
+                                r.status = SendStatus::Pending;
+                            }
+                            changed = true;
+                        }
+                    });
                 });
 
                 // Write back changes
                 if changed {
                     self.templates[idx] = template;
-                    self.save_templates();
+                    self.templates_edited();
                 }
             } else {
                 ui.vertical_centered(|ui| {
@@ -572,18 +2480,120 @@ impl eframe::App for EmailApp {
     }
 }
 
-/// Wraps text in the body with HTML tags. Since egui TextEdit doesn't expose
-/// selection ranges directly, this function appends empty tags at the end
-/// of the body so the user can type formatted content within them.
-fn wrap_body_selection(body: &mut String, open_tag: &str, close_tag: &str) {
-    // Append opening and closing tag so user can type between them
-    body.push_str(open_tag);
-    body.push_str(close_tag);
+/// A combo box choosing one of the CSV `headers`, or none.
+fn column_picker(
+    ui: &mut egui::Ui,
+    id: impl std::hash::Hash,
+    headers: &[String],
+    selected: &mut Option<usize>,
+) {
+    let text = selected
+        .and_then(|i| headers.get(i))
+        .map(String::as_str)
+        .unwrap_or("(none)");
+    egui::ComboBox::from_id_salt(id)
+        .selected_text(text)
+        .show_ui(ui, |ui| {
+            ui.selectable_value(selected, None, "(none)");
+            for (i, header) in headers.iter().enumerate() {
+                ui.selectable_value(selected, Some(i), header);
+            }
+        });
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Formats Unix seconds as `YYYY-MM-DD HH:MM UTC`.
+fn format_timestamp(secs: u64) -> String {
+    // Civil-from-days conversion (Howard Hinnant's algorithm).
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3600,
+        (rem % 3600) / 60
+    )
+}
+
+/// Shows a recipient's delivery state in the recipients grid.
+fn status_label(ui: &mut egui::Ui, status: &SendStatus) {
+    match status {
+        SendStatus::Pending => {
+            ui.colored_label(egui::Color32::from_rgb(150, 150, 150), "Pending");
+        }
+        SendStatus::Sent { at, message_id } => {
+            ui.colored_label(egui::Color32::from_rgb(80, 200, 80), "✓ Sent")
+                .on_hover_text(format!("{}\n{}", format_timestamp(*at), message_id));
+        }
+        SendStatus::Failed { at, error } => {
+            ui.colored_label(egui::Color32::from_rgb(220, 80, 80), "✗ Failed")
+                .on_hover_text(format!("{}\n{}", format_timestamp(*at), error));
+        }
+    }
+}
+
+/// A formatting command for the body editor, applied to its selection.
+enum BodyAction {
+    Wrap(&'static str, &'static str),
+    Link,
+    List,
+}
+
+fn body_editor_id(template_id: &str) -> egui::Id {
+    egui::Id::new(("body_editor", template_id))
+}
+
+/// The body editor's selection in characters. Without a remembered cursor,
+/// edits go at the end of the body.
+fn body_selection(state: &egui::text_edit::TextEditState, body: &str) -> std::ops::Range<usize> {
+    let end = body.chars().count();
+    state
+        .cursor
+        .char_range()
+        .map(|r| {
+            let [min, max] = r.sorted();
+            min.index..max.index
+        })
+        .unwrap_or(end..end)
+}
+
+/// Selects `range` (in characters) in the body editor and gives it focus, so
+/// the user can keep typing after a toolbar action.
+fn set_body_selection(
+    ctx: &egui::Context,
+    state: &mut egui::text_edit::TextEditState,
+    id: egui::Id,
+    range: std::ops::Range<usize>,
+) {
+    state.cursor.set_char_range(Some(egui::text::CCursorRange::two(
+        egui::text::CCursor::new(range.start),
+        egui::text::CCursor::new(range.end),
+    )));
+    state.clone().store(ctx, id);
+    ctx.memory_mut(|m| m.request_focus(id));
 }
 
-/// Renders a simple HTML preview in egui, supporting <b>, <i>, <u>, <a>, and <br> tags.
+/// Renders a simple HTML preview in egui, supporting <b>, <i>, <u>, <a>, <br>,
+/// headings, lists and inline images.
 /// This provides an approximate visual preview of how the email will look.
-fn render_html_preview(ui: &mut egui::Ui, html: &str) {
+fn render_html_preview(ui: &mut egui::Ui, html: &str, images: &[InlineImage]) {
     // Parse the HTML into segments with formatting info
     let segments = parse_html_segments(html);
 
@@ -594,6 +2604,30 @@ fn render_html_preview(ui: &mut egui::Ui, html: &str) {
                 ui.end_row();
                 continue;
             }
+            if let Some(src) = &segment.image {
+                let path = src
+                    .strip_prefix("cid:")
+                    .and_then(|cid| images.iter().find(|img| img.cid == cid))
+                    .map(InlineImage::file)
+                    .filter(|path| path.is_file());
+                match path {
+                    Some(path) => {
+                        ui.add(
+                            egui::Image::new(format!("file://{}", path.display()))
+                                .max_width(400.0)
+                                .max_height(300.0),
+                        )
+                        .on_hover_text(src);
+                    }
+                    None => {
+                        ui.colored_label(
+                            egui::Color32::from_rgb(150, 150, 150),
+                            format!("[🖼 {}]", if segment.text.is_empty() { src } else { &segment.text }),
+                        );
+                    }
+                }
+                continue;
+            }
             let mut text = egui::RichText::new(&segment.text);
             if segment.bold {
                 text = text.strong();
@@ -621,6 +2655,26 @@ struct HtmlSegment {
     underline: bool,
     is_link: bool,
     is_newline: bool,
+    /// `src` of an `<img>`; `text` holds its alt text.
+    image: Option<String>,
+}
+
+/// Starts a new line unless the output is empty or already at one. Returns
+/// whether a break was added.
+fn ensure_line_break(segments: &mut Vec<HtmlSegment>) -> bool {
+    let needed = segments.last().is_some_and(|last| !last.is_newline);
+    if needed {
+        segments.push(HtmlSegment {
+            text: String::new(),
+            bold: false,
+            italic: false,
+            underline: false,
+            is_link: false,
+            is_newline: true,
+            image: None,
+        });
+    }
+    needed
 }
 
 /// Simple HTML parser that extracts text segments with their formatting state.
@@ -630,6 +2684,11 @@ fn parse_html_segments(html: &str) -> Vec<HtmlSegment> {
     let mut italic = false;
     let mut underline = false;
     let mut is_link = false;
+    // Open lists; ordered lists hold the next item number.
+    let mut lists: Vec<Option<usize>> = Vec::new();
+    // Set after a break added for a block tag, so that a source line break
+    // right after it (`</li>\n<li>`) doesn't add a blank line as well
+    let mut soft_break = false;
     let mut current_text = String::new();
     let mut chars = html.chars().peekable();
 
@@ -637,13 +2696,15 @@ fn parse_html_segments(html: &str) -> Vec<HtmlSegment> {
         if c == '<' {
             // Flush current text
             if !current_text.is_empty() {
+                soft_break = false;
                 segments.push(HtmlSegment {
-                    text: current_text.clone(),
+                    text: decode_entities(&current_text),
                     bold,
                     italic,
                     underline,
                     is_link,
                     is_newline: false,
+                    image: None,
                 });
                 current_text.clear();
             }
@@ -674,23 +2735,91 @@ fn parse_html_segments(html: &str) -> Vec<HtmlSegment> {
                         underline: false,
                         is_link: false,
                         is_newline: true,
+                        image: None,
+                    });
+                }
+                "/p" => {
+                    segments.push(HtmlSegment {
+                        text: String::new(),
+                        bold: false,
+                        italic: false,
+                        underline: false,
+                        is_link: false,
+                        is_newline: true,
+                        image: None,
+                    });
+                }
+                t if t == "p" || t.starts_with("p ") => soft_break |= ensure_line_break(&mut segments),
+                "/li" => soft_break |= ensure_line_break(&mut segments),
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => bold = true,
+                "/h1" | "/h2" | "/h3" | "/h4" | "/h5" | "/h6" => bold = false,
+                "ul" => lists.push(None),
+                "/ul" | "/ol" => {
+                    lists.pop();
+                }
+                t if t == "ol" || t.starts_with("ol ") => {
+                    let start = t
+                        .split("start=\"")
+                        .nth(1)
+                        .and_then(|v| v.split('"').next())
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(1);
+                    lists.push(Some(start));
+                }
+                "li" => {
+                    // Items on one line, e.g. `<ul><li>a</li><li>b</li></ul>`
+                    ensure_line_break(&mut segments);
+                    soft_break = false;
+                    let indent = "    ".repeat(lists.len().saturating_sub(1));
+                    let marker = match lists.last_mut() {
+                        Some(Some(n)) => {
+                            *n += 1;
+                            format!("{}{}. ", indent, *n - 1)
+                        }
+                        _ => format!("{}• ", indent),
+                    };
+                    segments.push(HtmlSegment {
+                        text: marker,
+                        bold: false,
+                        italic: false,
+                        underline: false,
+                        is_link: false,
+                        is_newline: false,
+                        image: None,
                     });
                 }
                 t if t.starts_with("a ") || t == "a" => {
                     is_link = true;
                 }
+                t if t.starts_with("img ") => {
+                    segments.push(HtmlSegment {
+                        text: attribute(&tag, "alt").unwrap_or_default(),
+                        bold: false,
+                        italic: false,
+                        underline: false,
+                        is_link: false,
+                        is_newline: false,
+                        image: Some(attribute(&tag, "src").unwrap_or_default()),
+                    });
+                }
                 _ => {} // Ignore unknown tags
             }
         } else if c == '\n' {
+            if soft_break && current_text.trim().is_empty() {
+                current_text.clear();
+                soft_break = false;
+                continue;
+            }
             // Flush current text before newline
             if !current_text.is_empty() {
                 segments.push(HtmlSegment {
-                    text: current_text.clone(),
+                    text: decode_entities(&current_text),
                     bold,
                     italic,
                     underline,
                     is_link,
                     is_newline: false,
+                    image: None,
                 });
                 current_text.clear();
             }
@@ -701,6 +2830,7 @@ fn parse_html_segments(html: &str) -> Vec<HtmlSegment> {
                 underline: false,
                 is_link: false,
                 is_newline: true,
+                image: None,
             });
         } else {
             current_text.push(c);
@@ -710,15 +2840,57 @@ fn parse_html_segments(html: &str) -> Vec<HtmlSegment> {
     // Flush remaining text
     if !current_text.is_empty() {
         segments.push(HtmlSegment {
-            text: current_text,
+            text: decode_entities(&current_text),
             bold,
             italic,
             underline,
             is_link,
             is_newline: false,
+            image: None,
         });
     }
 
     segments
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The preview's text, one line per rendered line.
+    fn preview_lines(html: &str) -> Vec<String> {
+        let mut lines = vec![String::new()];
+        for segment in parse_html_segments(html) {
+            if segment.is_newline {
+                lines.push(String::new());
+            } else {
+                lines.last_mut().unwrap().push_str(&segment.text);
+            }
+        }
+        lines.retain(|line| !line.is_empty());
+        lines
+    }
+
+    #[test]
+    fn single_line_lists_break_per_item() {
+        assert_eq!(preview_lines("<ul><li>a</li><li>b</li></ul>"), ["• a", "• b"]);
+        assert_eq!(
+            preview_lines("<p>Steps:</p><ol><li>one</li><li>two</li></ol><p>Done</p>"),
+            ["Steps:", "1. one", "2. two", "Done"]
+        );
+    }
+
+    #[test]
+    fn paragraphs_break_without_closing_tags() {
+        assert_eq!(preview_lines("Hi<p>first<p class=\"x\">second"), ["Hi", "first", "second"]);
+    }
+
+    #[test]
+    fn multi_line_lists_get_no_blank_lines() {
+        let segments = parse_html_segments("<ul>\n<li>a</li>\n<li>b</li>\n</ul>");
+        let breaks = segments.iter().filter(|s| s.is_newline).count();
+        // The source's own line breaks, none doubled up
+        assert_eq!(breaks, 3);
+        assert_eq!(preview_lines("<ul>\n<li>a</li>\n<li>b</li>\n</ul>"), ["• a", "• b"]);
+    }
+}
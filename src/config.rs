@@ -1,27 +1,549 @@
+use std::path::{Path, PathBuf};
 use config::Config;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use crate::paths;
+use crate::secret::{PasswordSource, Secret};
 
-#[derive(Debug, Clone, Deserialize)]
+/// How the connection to the SMTP server is secured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// TLS from the first byte (SMTPS), normally on port 465.
+    #[default]
+    Implicit,
+    /// Plaintext connection that must be upgraded with STARTTLS, normally on port 587.
+    Starttls,
+    /// Upgrade with STARTTLS when the server offers it, otherwise stay in plaintext.
+    Opportunistic,
+    /// No encryption at all. Only meant for local relays and test sinks.
+    None,
+}
+
+impl TlsMode {
+    pub const ALL: [TlsMode; 4] = [
+        TlsMode::Implicit,
+        TlsMode::Starttls,
+        TlsMode::Opportunistic,
+        TlsMode::None,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TlsMode::Implicit => "Implicit TLS",
+            TlsMode::Starttls => "STARTTLS",
+            TlsMode::Opportunistic => "STARTTLS if offered",
+            TlsMode::None => "None (local relays only)",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            TlsMode::Implicit => 465,
+            TlsMode::Starttls | TlsMode::Opportunistic => 587,
+            TlsMode::None => 25,
+        }
+    }
+}
+
+/// Where built messages go. Everything except `Smtp` is for testing and dry runs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Smtp,
+    /// Write each message as an `.eml` file into `path`.
+    File { path: PathBuf },
+    /// Pipe each message to a local `sendmail`-compatible binary.
+    Sendmail {
+        #[serde(default)]
+        command: Option<String>,
+    },
+    /// Keep messages in memory and list them in the status log; nothing is delivered.
+    Memory,
+}
+
+/// Which OAuth2 endpoints and scope a profile signs in with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OAuthProvider {
+    #[default]
+    Google,
+    Microsoft,
+    /// Any other provider; `auth_url`, `token_url` and `scope` must be set.
+    Custom,
+}
+
+/// OAuth2 sign-in for a profile, used instead of `password` (XOAUTH2).
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct OAuth2Settings {
+    #[serde(default)]
+    pub provider: OAuthProvider,
+    pub client_id: String,
+    /// Required by Google even for desktop apps; Microsoft public clients have none.
+    #[serde(default)]
+    pub client_secret: Option<Secret>,
+    /// Microsoft Entra tenant; defaults to `common`.
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// Overrides the provider's authorization endpoint.
+    #[serde(default)]
+    pub auth_url: Option<String>,
+    /// Overrides the provider's token endpoint.
+    #[serde(default)]
+    pub token_url: Option<String>,
+    /// Overrides the provider's scope (space separated).
+    #[serde(default)]
+    pub scope: Option<String>,
+}
+
+impl OAuthProvider {
+    pub const ALL: [OAuthProvider; 3] = [
+        OAuthProvider::Google,
+        OAuthProvider::Microsoft,
+        OAuthProvider::Custom,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            OAuthProvider::Google => "Google",
+            OAuthProvider::Microsoft => "Microsoft 365",
+            OAuthProvider::Custom => "Custom",
+        }
+    }
+}
+
+impl OAuth2Settings {
+    pub fn auth_url(&self) -> String {
+        self.auth_url.clone().unwrap_or_else(|| match self.provider {
+            OAuthProvider::Google => "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+            OAuthProvider::Microsoft => format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/authorize",
+                self.tenant()
+            ),
+            OAuthProvider::Custom => String::new(),
+        })
+    }
+
+    pub fn token_url(&self) -> String {
+        self.token_url.clone().unwrap_or_else(|| match self.provider {
+            OAuthProvider::Google => "https://oauth2.googleapis.com/token".to_string(),
+            OAuthProvider::Microsoft => format!(
+                "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+                self.tenant()
+            ),
+            OAuthProvider::Custom => String::new(),
+        })
+    }
+
+    pub fn scope(&self) -> String {
+        self.scope.clone().unwrap_or_else(|| match self.provider {
+            OAuthProvider::Google => "https://mail.google.com/".to_string(),
+            // offline_access is what makes Microsoft hand out a refresh token
+            OAuthProvider::Microsoft => "https://outlook.office.com/SMTP.Send offline_access".to_string(),
+            OAuthProvider::Custom => String::new(),
+        })
+    }
+
+    fn tenant(&self) -> &str {
+        self.tenant.as_deref().unwrap_or("common")
+    }
+}
+
+/// Key type of a DKIM signing key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DkimAlgorithm {
+    /// PKCS#1 PEM file (`BEGIN RSA PRIVATE KEY`).
+    #[default]
+    Rsa,
+    /// File holding the base64-encoded 32-byte private key.
+    Ed25519,
+}
+
+/// DKIM signing for a sender profile. The public key must be published as a
+/// TXT record at `<selector>._domainkey.<domain>`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DkimSettings {
+    pub selector: String,
+    /// Signing domain (`d=`); should match the From address's domain for DMARC.
+    pub domain: String,
+    pub private_key_path: PathBuf,
+    #[serde(default)]
+    pub algorithm: DkimAlgorithm,
+}
+
+/// One sender account. A settings file holds one or more of these as profiles.
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct SmtpConfig {
+    /// Profile name shown in the sender picker.
+    #[serde(default = "default_profile_name")]
+    pub name: String,
     pub host: String,
+    /// Defaults to the standard port for the chosen `tls` mode.
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: TlsMode,
     pub username: String,
-    pub password: String,
+    /// Plaintext password; prefer one of the `password_*` sources below. Leave
+    /// all of them unset for relays that don't require authentication.
+    #[serde(default, skip_serializing_if = "Secret::is_empty")]
+    pub password: Secret,
+    /// Environment variable holding the password.
+    #[serde(default)]
+    pub password_env: Option<String>,
+    /// File holding the password; must not be accessible by other users.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+    /// Shell command that prints the password, e.g. `pass show smtp/work`.
+    #[serde(default)]
+    pub password_command: Option<String>,
+    /// Look the password up in the OS keyring under `username`.
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub password_keyring: bool,
     pub from_name: String,
+    /// Address used in the `From`/`Reply-To` headers; defaults to `username`.
+    #[serde(default)]
+    pub from_address: Option<String>,
     #[serde(default = "default_delay")]
     pub send_delay_ms: u64,
+    /// How many times a temporary failure is retried before giving up.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Backoff before the first retry; doubled for each further attempt.
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay_ms: u64,
+    /// Upper bound for the backoff between retries.
+    #[serde(default = "default_retry_max_delay")]
+    pub retry_max_delay_ms: u64,
+    /// Limit on the encoded size of all attachments and inline images in one
+    /// message, in megabytes; 0 disables the check.
+    #[serde(default = "default_attachment_limit")]
+    pub attachment_limit_mb: u64,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub transport: TransportKind,
+    /// Authenticate with OAuth2 (XOAUTH2) instead of `password` when set.
+    #[serde(default)]
+    pub oauth2: Option<OAuth2Settings>,
+    /// Sign outgoing messages with DKIM when set.
+    #[serde(default)]
+    pub dkim: Option<DkimSettings>,
 }
 
 fn default_delay() -> u64 {
     2000
 }
 
-impl SmtpConfig {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_delay() -> u64 {
+    5000
+}
+
+fn default_retry_max_delay() -> u64 {
+    60_000
+}
+
+fn default_attachment_limit() -> u64 {
+    25
+}
+
+fn default_profile_name() -> String {
+    "Default".to_string()
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+/// All sender profiles from `Settings.toml`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Settings {
+    pub profiles: Vec<SmtpConfig>,
+}
+
+impl Settings {
+    /// Loads `[[profiles]]` entries, or a single top-level account for older files.
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
         let settings = Config::builder()
-            .add_source(config::File::with_name("src/Settings"))
+            .add_source(config::File::new(
+                &path.to_string_lossy(),
+                config::FileFormat::Toml,
+            ))
             .build()?;
-        let cfg: SmtpConfig = settings.try_deserialize()?;
-        Ok(cfg)
+
+        let profiles = match settings.get::<Vec<SmtpConfig>>("profiles") {
+            Ok(profiles) => profiles,
+            Err(config::ConfigError::NotFound(_)) => vec![settings.try_deserialize::<SmtpConfig>()?],
+            Err(e) => return Err(e.into()),
+        };
+
+        let settings = Self { profiles };
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Writes the profiles as `[[profiles]]` entries. The file is readable by
+    /// the current user only, since it may hold a password.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        self.validate()?;
+        let body = toml::to_string(self).map_err(|e| e.to_string())?;
+        let data = format!(
+            "# Sender profiles for email-senderr; see the README for every option.\n\n{}",
+            body
+        );
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        paths::write_private(path, data.as_bytes()).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.profiles.is_empty() {
+            return Err("no sender profiles configured".to_string());
+        }
+        for (i, profile) in self.profiles.iter().enumerate() {
+            profile
+                .validate()
+                .map_err(|e| format!("profile '{}': {}", profile.name, e))?;
+            if self.profiles[..i].iter().any(|p| p.name == profile.name) {
+                return Err(format!("duplicate profile name '{}'", profile.name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up a profile by name, or the first profile when no name is set.
+    /// A name that no longer exists is an error rather than a quiet switch to
+    /// another mailbox.
+    pub fn profile(&self, name: Option<&str>) -> Result<&SmtpConfig, String> {
+        match name {
+            Some(name) => self.profiles.iter().find(|p| p.name == name).ok_or_else(|| {
+                format!(
+                    "sender profile '{}' no longer exists; choose another under Send from",
+                    name
+                )
+            }),
+            None => self
+                .profiles
+                .first()
+                .ok_or_else(|| "no sender profile is configured".to_string()),
+        }
+    }
+}
+
+impl SmtpConfig {
+    /// A profile with every optional setting at its default.
+    pub fn new(name: String, host: String, username: String, from_name: String) -> Self {
+        Self {
+            name,
+            host,
+            port: None,
+            tls: TlsMode::default(),
+            username,
+            password: Secret::default(),
+            password_env: None,
+            password_file: None,
+            password_command: None,
+            password_keyring: false,
+            from_name,
+            from_address: None,
+            send_delay_ms: default_delay(),
+            max_retries: default_max_retries(),
+            retry_delay_ms: default_retry_delay(),
+            retry_max_delay_ms: default_retry_max_delay(),
+            attachment_limit_mb: default_attachment_limit(),
+            transport: TransportKind::default(),
+            oauth2: None,
+            dkim: None,
+        }
+    }
+
+    pub fn sender_address(&self) -> &str {
+        self.from_address.as_deref().unwrap_or(&self.username)
+    }
+
+    /// Where the password comes from, or `None` when the profile doesn't use
+    /// one. Fails when more than one source is set.
+    pub fn password_source(&self) -> Result<Option<PasswordSource>, String> {
+        let mut sources = Vec::new();
+        if !self.password.is_empty() {
+            sources.push(PasswordSource::Plain(self.password.clone()));
+        }
+        if let Some(name) = &self.password_env {
+            sources.push(PasswordSource::Env(name.clone()));
+        }
+        if let Some(path) = &self.password_file {
+            sources.push(PasswordSource::File(paths::settings_path(path)));
+        }
+        if let Some(command) = &self.password_command {
+            sources.push(PasswordSource::Command(command.clone()));
+        }
+        if self.password_keyring {
+            sources.push(PasswordSource::Keyring);
+        }
+        if sources.len() > 1 {
+            return Err(
+                "set only one of password, password_env, password_file, password_command and password_keyring"
+                    .to_string(),
+            );
+        }
+        Ok(sources.pop())
+    }
+
+    /// Reads the SMTP password from its source, if the profile has one.
+    pub fn password(&self) -> Result<Option<Secret>, String> {
+        self.password_source()?
+            .map(|source| source.read(&self.username))
+            .transpose()
+            .map_err(|e| format!("password for '{}': {}", self.name, e))
+    }
+
+    /// The attachment size limit in bytes, if any.
+    pub fn attachment_limit(&self) -> Option<u64> {
+        (self.attachment_limit_mb > 0).then(|| self.attachment_limit_mb * 1024 * 1024)
+    }
+
+    /// Backoff before retry number `attempt` (starting at 1).
+    pub fn retry_delay(&self, attempt: u32) -> std::time::Duration {
+        let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+        let delay = self
+            .retry_delay_ms
+            .saturating_mul(factor)
+            .min(self.retry_max_delay_ms);
+        std::time::Duration::from_millis(delay)
+    }
+
+    /// The port to connect to, falling back to the default for the TLS mode.
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.tls.default_port())
+    }
+
+    /// Rejects port/TLS combinations that cannot work, and plaintext
+    /// authentication against anything other than a local relay.
+    pub fn validate(&self) -> Result<(), String> {
+        let has_password = self.password_source()?.is_some();
+        let port = self.port();
+        match self.tls {
+            TlsMode::Implicit if port == 25 || port == 587 => {
+                return Err(format!(
+                    "port {} expects STARTTLS, but tls = \"implicit\"; set tls = \"starttls\" or use port 465",
+                    port
+                ));
+            }
+            TlsMode::Starttls | TlsMode::Opportunistic if port == 465 => {
+                return Err(
+                    "port 465 expects implicit TLS; set tls = \"implicit\" or use port 587".to_string(),
+                );
+            }
+            TlsMode::None
+                if (has_password || self.oauth2.is_some()) && !self.is_local_host() =>
+            {
+                return Err(format!(
+                    "refusing to send credentials unencrypted to {}; plaintext (tls = \"none\") is only allowed for local relays",
+                    self.host
+                ));
+            }
+            _ => {}
+        }
+        if let Some(oauth) = &self.oauth2 {
+            if oauth.client_id.trim().is_empty() {
+                return Err("oauth2 needs a client_id".to_string());
+            }
+            if oauth.auth_url().is_empty() || oauth.token_url().is_empty() || oauth.scope().is_empty() {
+                return Err(
+                    "oauth2 with provider = \"custom\" needs auth_url, token_url and scope".to_string(),
+                );
+            }
+        }
+        if let Some(dkim) = &self.dkim {
+            // The key file itself is checked before sending, so a moved key
+            // doesn't keep the app from starting
+            if dkim.selector.trim().is_empty() || dkim.domain.trim().is_empty() {
+                return Err("dkim needs both a selector and a domain".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn is_local_host(&self) -> bool {
+        matches!(self.host.as_str(), "localhost" | "127.0.0.1" | "::1")
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        let profile = |name: &str| {
+            SmtpConfig::new(name.into(), "smtp.example.com".into(), "me@example.com".into(), "Me".into())
+        };
+        Settings { profiles: vec![profile("Sales"), profile("Support")] }
+    }
+
+    #[test]
+    fn profile_lookup() {
+        let settings = settings();
+        assert_eq!(settings.profile(None).unwrap().name, "Sales");
+        assert_eq!(settings.profile(Some("Support")).unwrap().name, "Support");
+    }
+
+    #[test]
+    fn missing_profile_is_an_error() {
+        let err = settings().profile(Some("Marketing")).unwrap_err();
+        assert!(err.contains("'Marketing' no longer exists"), "{}", err);
+        assert!(Settings { profiles: Vec::new() }.profile(None).is_err());
+    }
+
+    fn profile(host: &str, tls: TlsMode, port: Option<u16>) -> SmtpConfig {
+        let mut config = SmtpConfig::new("Test".into(), host.into(), "me@example.com".into(), "Me".into());
+        config.tls = tls;
+        config.port = port;
+        config
+    }
+
+    #[test]
+    fn default_port_follows_the_tls_mode() {
+        assert_eq!(profile("smtp.example.com", TlsMode::Implicit, None).port(), 465);
+        assert_eq!(profile("smtp.example.com", TlsMode::Starttls, None).port(), 587);
+        assert_eq!(profile("smtp.example.com", TlsMode::Opportunistic, None).port(), 587);
+        assert_eq!(profile("localhost", TlsMode::None, None).port(), 25);
+        assert_eq!(profile("smtp.example.com", TlsMode::Starttls, Some(2525)).port(), 2525);
+        for tls in [TlsMode::Implicit, TlsMode::Starttls, TlsMode::Opportunistic] {
+            assert_eq!(profile("smtp.example.com", tls, None).validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn tls_mode_must_suit_the_port() {
+        for port in [25, 587] {
+            let err = profile("smtp.example.com", TlsMode::Implicit, Some(port)).validate().unwrap_err();
+            assert!(err.starts_with(&format!("port {} expects STARTTLS", port)), "{}", err);
+        }
+        for tls in [TlsMode::Starttls, TlsMode::Opportunistic] {
+            let err = profile("smtp.example.com", tls, Some(465)).validate().unwrap_err();
+            assert!(err.starts_with("port 465 expects implicit TLS"), "{}", err);
+        }
+        assert_eq!(profile("smtp.example.com", TlsMode::Implicit, Some(2465)).validate(), Ok(()));
+    }
+
+    #[test]
+    fn plaintext_auth_only_to_localhost() {
+        let with_password = |host: &str| {
+            let mut config = profile(host, TlsMode::None, None);
+            config.password = Secret::new("hunter2");
+            config
+        };
+        for host in ["localhost", "127.0.0.1", "::1"] {
+            assert_eq!(with_password(host).validate(), Ok(()));
+        }
+        let err = with_password("smtp.example.com").validate().unwrap_err();
+        assert!(err.starts_with("refusing to send credentials unencrypted"), "{}", err);
+        assert!(!err.contains("hunter2"));
+        // Without credentials there is nothing to leak
+        assert_eq!(profile("relay.example.com", TlsMode::None, None).validate(), Ok(()));
+    }
+}
@@ -0,0 +1,272 @@
+use crate::template::{EmailTemplate, Recipient, SendStatus};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A CSV file read for import: the header row, the data rows that could be
+/// read, and a description of every row that couldn't.
+#[derive(Debug, Clone)]
+pub struct CsvTable {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    pub errors: Vec<String>,
+}
+
+/// Which CSV column feeds the email address, the optional display name and
+/// Cc/Bcc lists, and each placeholder.
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    pub email: Option<usize>,
+    pub display_name: Option<usize>,
+    pub cc: Option<usize>,
+    pub bcc: Option<usize>,
+    pub args: Vec<(String, Option<usize>)>,
+    /// Also import columns not mapped to a placeholder, keyed by header.
+    pub include_unmapped: bool,
+}
+
+impl ColumnMapping {
+    /// Matches headers to the email field and placeholders by name, ignoring
+    /// case, surrounding whitespace and `_`/`-`/space differences.
+    pub fn guess(headers: &[String], placeholders: &[String]) -> Self {
+        let find = |name: &str| {
+            let wanted = normalize_header(name);
+            headers.iter().position(|h| normalize_header(h) == wanted)
+        };
+        let email = find("email")
+            .or_else(|| find("email address"))
+            .or_else(|| find("e-mail"));
+        let display_name = find("display name").or_else(|| find("full name"));
+        let args = placeholders
+            .iter()
+            .map(|p| (p.clone(), find(p)))
+            .collect();
+        Self {
+            email,
+            display_name,
+            cc: find("cc"),
+            bcc: find("bcc"),
+            args,
+            include_unmapped: false,
+        }
+    }
+}
+
+fn normalize_header(header: &str) -> String {
+    header
+        .trim()
+        .chars()
+        .filter(|c| !matches!(c, '_' | '-' | ' '))
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+pub fn read_csv(path: &Path) -> Result<CsvTable, Box<dyn std::error::Error>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+
+    let mut rows = Vec::new();
+    let mut errors = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        // Line 1 is the header row.
+        let line = i + 2;
+        match record {
+            Ok(record) if record.len() != headers.len() => errors.push(format!(
+                "row {}: expected {} fields, found {}",
+                line,
+                headers.len(),
+                record.len()
+            )),
+            Ok(record) => rows.push(record.iter().map(str::to_string).collect()),
+            Err(e) => errors.push(format!("row {}: {}", line, e)),
+        }
+    }
+
+    Ok(CsvTable {
+        headers,
+        rows,
+        errors,
+    })
+}
+
+/// Turns table rows into recipients using `mapping`. Rows with a missing or
+/// unparsable email address are skipped and described in the returned errors.
+pub fn map_rows(table: &CsvTable, mapping: &ColumnMapping) -> (Vec<Recipient>, Vec<String>) {
+    let mut recipients = Vec::new();
+    let mut errors = Vec::new();
+    let Some(email_col) = mapping.email else {
+        errors.push("no column selected for the email address".to_string());
+        return (recipients, errors);
+    };
+    let fixed = [Some(email_col), mapping.display_name, mapping.cc, mapping.bcc];
+
+    for (i, row) in table.rows.iter().enumerate() {
+        let email = row[email_col].clone();
+        if email.is_empty() {
+            errors.push(format!("data row {}: email address is empty", i + 1));
+            continue;
+        }
+        let cell = |col: Option<usize>| col.map(|c| row[c].clone()).unwrap_or_default();
+        let recipient = Recipient {
+            email,
+            display_name: cell(mapping.display_name),
+            cc: cell(mapping.cc),
+            bcc: cell(mapping.bcc),
+            ..Default::default()
+        };
+        if let Err(e) = recipient.to_mailboxes() {
+            errors.push(format!("data row {}: {}", i + 1, e));
+            continue;
+        }
+
+        let mut args = HashMap::new();
+        if mapping.include_unmapped {
+            for (col, header) in table.headers.iter().enumerate() {
+                let mapped = fixed.contains(&Some(col)) || mapping.args.iter().any(|(_, c)| *c == Some(col));
+                if !mapped && !header.is_empty() {
+                    args.insert(header.clone(), row[col].clone());
+                }
+            }
+        }
+        for (key, col) in &mapping.args {
+            args.insert(key.clone(), col.map(|c| row[c].clone()).unwrap_or_default());
+        }
+
+        recipients.push(Recipient { args, ..recipient });
+    }
+
+    (recipients, errors)
+}
+
+/// Writes the template's recipients with their arguments and send status.
+pub fn export_csv(path: &Path, template: &EmailTemplate) -> Result<(), Box<dyn std::error::Error>> {
+    let mut keys = template.extract_placeholders();
+    for recipient in &template.recipients {
+        let mut extra: Vec<&String> = recipient.args.keys().filter(|k| !keys.contains(k)).collect();
+        extra.sort();
+        keys.extend(extra.into_iter().cloned());
+    }
+
+    let mut writer = csv::Writer::from_path(path)?;
+    let mut header = ["email", "display_name", "cc", "bcc"].map(String::from).to_vec();
+    header.extend(keys.iter().cloned());
+    header.extend(["status", "sent_at", "message_id", "error"].map(String::from));
+    writer.write_record(&header)?;
+
+    for recipient in &template.recipients {
+        let mut record = vec![
+            recipient.email.clone(),
+            recipient.display_name.clone(),
+            recipient.cc.clone(),
+            recipient.bcc.clone(),
+        ];
+        record.extend(
+            keys.iter()
+                .map(|k| recipient.args.get(k).cloned().unwrap_or_default()),
+        );
+        let (status, at, message_id, error) = match &recipient.status {
+            SendStatus::Pending => ("pending", String::new(), "", ""),
+            SendStatus::Sent { at, message_id } => ("sent", at.to_string(), message_id.as_str(), ""),
+            SendStatus::Failed { at, error } => ("failed", at.to_string(), "", error.as_str()),
+        };
+        record.extend([status.to_string(), at, message_id.to_string(), error.to_string()]);
+        writer.write_record(&record)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn import_and_export_round_trip() {
+        let dir = std::env::temp_dir().join(format!("email-senderr-contacts-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("contacts.csv");
+        std::fs::write(
+            &input,
+            "\u{feff}Email,Full Name,CC,first_name,Note\n\
+             a@example.com,\"Doe, Jane\",,Jane,\"line one\nline two\"\n\
+             b@example.com,Bob,,Bob\n\
+             c@example.com,Carol,boss@example.com,Carol,\"say \"\"hi\"\", then go\"\n",
+        )
+        .unwrap();
+
+        let table = read_csv(&input).unwrap();
+        assert_eq!(table.headers, strings(&["Email", "Full Name", "CC", "first_name", "Note"]));
+        assert_eq!(table.errors, vec!["row 3: expected 5 fields, found 4"]);
+        assert_eq!(table.rows.len(), 2);
+
+        let placeholders = strings(&["first_name", "note"]);
+        let mapping = ColumnMapping::guess(&table.headers, &placeholders);
+        assert_eq!(mapping.email, Some(0));
+        assert_eq!(mapping.display_name, Some(1));
+        assert_eq!(mapping.cc, Some(2));
+        assert_eq!(mapping.bcc, None);
+        assert_eq!(mapping.args, vec![("first_name".to_string(), Some(3)), ("note".to_string(), Some(4))]);
+
+        let (recipients, errors) = map_rows(&table, &mapping);
+        assert!(errors.is_empty());
+        assert_eq!(recipients[0].display_name, "Doe, Jane");
+        assert_eq!(recipients[0].args["note"], "line one\nline two");
+        assert_eq!(recipients[1].cc, "boss@example.com");
+        assert_eq!(recipients[1].args["note"], "say \"hi\", then go");
+
+        let mut template = EmailTemplate::new("Test".to_string());
+        template.subject = "Hi {first_name}".to_string();
+        template.body = "<p>{note}</p>".to_string();
+        template.recipients = recipients;
+        template.recipients[0].status = SendStatus::Sent {
+            at: 1_700_000_000,
+            message_id: "<1@example.com>".to_string(),
+        };
+        template.recipients[1].status = SendStatus::Failed {
+            at: 1_700_000_060,
+            error: "550 mailbox unavailable, \"really\"".to_string(),
+        };
+        let output = dir.join("export.csv");
+        export_csv(&output, &template).unwrap();
+
+        let exported = read_csv(&output).unwrap();
+        assert!(exported.errors.is_empty());
+        assert_eq!(
+            exported.headers,
+            strings(&[
+                "email", "display_name", "cc", "bcc", "first_name", "note", "status", "sent_at", "message_id",
+                "error",
+            ])
+        );
+        assert_eq!(
+            exported.rows,
+            vec![
+                strings(&[
+                    "a@example.com", "Doe, Jane", "", "", "Jane", "line one\nline two", "sent", "1700000000",
+                    "<1@example.com>", "",
+                ]),
+                strings(&[
+                    "c@example.com", "Carol", "boss@example.com", "", "Carol", "say \"hi\", then go", "failed",
+                    "1700000060", "", "550 mailbox unavailable, \"really\"",
+                ]),
+            ]
+        );
+
+        // Importing the export again gives the same recipients
+        let mapping = ColumnMapping::guess(&exported.headers, &placeholders);
+        let (again, errors) = map_rows(&exported, &mapping);
+        assert!(errors.is_empty());
+        for (a, b) in again.iter().zip(&template.recipients) {
+            assert_eq!((&a.email, &a.display_name, &a.cc, &a.bcc), (&b.email, &b.display_name, &b.cc, &b.bcc));
+            assert_eq!(a.args, b.args);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
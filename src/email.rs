@@ -1,15 +1,235 @@
-use crate::config::SmtpConfig;
-use crate::template::{EmailTemplate, Recipient};
-use lettre::message::{header::ContentType, Attachment, MultiPart, SinglePart};
-use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
-use std::sync::mpsc::Sender;
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lettre::message::dkim::{
+    DkimCanonicalization, DkimCanonicalizationType, DkimConfig, DkimSigningAlgorithm, DkimSigningKey,
+};
+use lettre::message::header::{ContentType, HeaderName, HeaderValue};
+use lettre::message::{Attachment, MultiPart, SinglePart};
+use lettre::transport::smtp::authentication::{Credentials, Mechanism, DEFAULT_MECHANISMS};
+use lettre::transport::smtp::client::{SmtpConnection, Tls, TlsParameters};
+use lettre::transport::smtp::extension::{ClientId, Extension};
+use lettre::transport::smtp::SmtpTransportBuilder;
+use lettre::{FileTransport, Message, SmtpTransport, Transport};
+use serde::Serialize;
+use crate::config::{DkimAlgorithm, DkimSettings, SmtpConfig, TlsMode, TransportKind};
+use crate::oauth::{self, OAuthToken, TokenError};
+use crate::paths;
+use crate::template::{parse_address_list, EmailTemplate, InlineImage, Recipient};
+
+/// Whether a failed send could succeed if tried again later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// 4xx replies and network trouble, e.g. greylisting or too many connections.
+    Transient,
+    /// 5xx replies, invalid addresses and messages that cannot be built.
+    Permanent,
+}
+
+impl FailureKind {
+    pub fn classify(error: &(dyn std::error::Error + 'static)) -> Self {
+        if let Some(e) = error.downcast_ref::<lettre::transport::smtp::Error>() {
+            if e.is_permanent() || e.is_response() || e.is_client() || e.is_tls() {
+                FailureKind::Permanent
+            } else {
+                // 4xx replies, timeouts, connection and network errors
+                FailureKind::Transient
+            }
+        } else if let Some(e) = error.downcast_ref::<lettre::transport::file::Error>() {
+            if e.is_io() {
+                FailureKind::Transient
+            } else {
+                FailureKind::Permanent
+            }
+        } else if let Some(e) = error.downcast_ref::<SendmailError>() {
+            match e {
+                // Couldn't start it or talk to it, or it asked to be tried later
+                SendmailError::Io(_) | SendmailError::Exit { code: Some(EX_TEMPFAIL), .. } => {
+                    FailureKind::Transient
+                }
+                SendmailError::Exit { .. } => FailureKind::Permanent,
+            }
+        } else if let Some(e) = error.downcast_ref::<TokenError>() {
+            if e.is_transient() {
+                FailureKind::Transient
+            } else {
+                FailureKind::Permanent
+            }
+        } else if error.is::<std::io::Error>() {
+            FailureKind::Transient
+        } else {
+            FailureKind::Permanent
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            FailureKind::Transient => "temporary",
+            FailureKind::Permanent => "permanent",
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum SendProgress {
-    Sent { index: usize, email: String },
-    Failed { index: usize, email: String, error: String },
+    Sent { index: usize, email: String, message_id: String },
+    Failed { index: usize, email: String, error: String, kind: FailureKind },
+    /// The transport kept the message instead of sending it; `summary` says what it was.
+    Recorded { index: usize, summary: String },
+    /// A temporary failure; the message will be retried after `delay`.
+    Retrying { index: usize, email: String, error: String, attempt: u32, delay: Duration },
+    Paused,
+    Resumed,
+    /// Sent instead of `Done` when the operator cancels; `remaining` recipients were not attempted.
+    Cancelled { remaining: usize },
     Done,
+    /// A dry run wrote one more message.
+    Written,
+    /// Sent instead of `Done` at the end of a dry run.
+    DryRunDone { out_dir: PathBuf, result: Result<DryRunManifest, String> },
+}
+
+/// Commands from the UI to a running bulk send. They take effect between
+/// messages, including during the throttle delay.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendControl {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+pub type TransportError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Delivers a built `Message`. Implemented for every lettre transport and for
+/// [`MemoryTransport`], so sending code doesn't care where messages end up.
+pub trait MailTransport: Send {
+    fn deliver(&self, message: &Message) -> Result<(), TransportError>;
+
+    /// A line for the status log about a message that was just delivered,
+    /// from transports that keep messages rather than send them.
+    fn summary(&self, _message: &Message) -> Option<String> {
+        None
+    }
+}
+
+impl<T> MailTransport for T
+where
+    T: Transport + Send,
+    T::Error: std::error::Error + Send + Sync + 'static,
+{
+    fn deliver(&self, message: &Message) -> Result<(), TransportError> {
+        self.send(message)?;
+        Ok(())
+    }
+}
+
+/// Keeps every message in memory and delivers nothing. Each one is reported
+/// in the status log with its recipients, subject and size.
+#[derive(Default)]
+pub struct MemoryTransport {
+    messages: Mutex<Vec<Message>>,
+}
+
+impl MailTransport for MemoryTransport {
+    fn deliver(&self, message: &Message) -> Result<(), TransportError> {
+        self.messages
+            .lock()
+            .map_err(|_| "message record is poisoned")?
+            .push(message.clone());
+        Ok(())
+    }
+
+    fn summary(&self, message: &Message) -> Option<String> {
+        let count = self.messages.lock().map(|m| m.len()).unwrap_or_default();
+        let to: Vec<String> = message.envelope().to().iter().map(|a| a.to_string()).collect();
+        Some(format!(
+            "Kept message {} in memory: to {}, subject \"{}\", {}",
+            count,
+            to.join(", "),
+            message.headers().get_raw("Subject").unwrap_or_default(),
+            format_size(message.formatted().len() as u64)
+        ))
+    }
+}
+
+/// The sysexits.h status for "try again later", e.g. a full queue.
+const EX_TEMPFAIL: i32 = 75;
+
+/// Why a message couldn't be handed to sendmail.
+#[derive(Debug)]
+pub enum SendmailError {
+    /// The binary couldn't be started, or writing to it failed.
+    Io(std::io::Error),
+    /// It ran and exited unsuccessfully; `code` is `None` when killed by a signal.
+    Exit { code: Option<i32>, stderr: String },
+}
+
+impl std::fmt::Display for SendmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendmailError::Io(e) => write!(f, "couldn't run sendmail: {}", e),
+            SendmailError::Exit { code: Some(code), stderr } => {
+                write!(f, "sendmail exited with status {}: {}", code, stderr.trim())
+            }
+            SendmailError::Exit { code: None, stderr } => {
+                write!(f, "sendmail was killed: {}", stderr.trim())
+            }
+        }
+    }
+}
+
+impl std::error::Error for SendmailError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SendmailError::Io(e) => Some(e),
+            SendmailError::Exit { .. } => None,
+        }
+    }
+}
+
+/// Pipes each message to a `sendmail`-compatible binary with the same
+/// arguments lettre's `SendmailTransport` uses, but keeps the exit status so
+/// a temporary failure can be retried.
+struct SendmailTransport {
+    command: OsString,
+}
+
+impl MailTransport for SendmailTransport {
+    fn deliver(&self, message: &Message) -> Result<(), TransportError> {
+        let envelope = message.envelope();
+        let mut command = Command::new(&self.command);
+        command.arg("-i");
+        if let Some(from) = envelope.from() {
+            command.arg("-f").arg(from);
+        }
+        command
+            .arg("--")
+            .args(envelope.to())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = command.spawn().map_err(SendmailError::Io)?;
+        // A binary that exits early closes the pipe; its status explains why better
+        let written = child
+            .stdin
+            .take()
+            .map_or(Ok(()), |mut stdin| stdin.write_all(&message.formatted()));
+        let output = child.wait_with_output().map_err(SendmailError::Io)?;
+        if !output.status.success() {
+            return Err(SendmailError::Exit {
+                code: output.status.code(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }
+            .into());
+        }
+        written.map_err(SendmailError::Io)?;
+        Ok(())
+    }
 }
 
 pub fn build_message(
@@ -17,15 +237,30 @@ pub fn build_message(
     template: &EmailTemplate,
     recipient: &Recipient,
 ) -> Result<Message, Box<dyn std::error::Error>> {
-    let from = format!("{} <{}>", config.from_name, config.username);
+    let from = format!("{} <{}>", config.from_name, config.sender_address());
     let rendered_subject = template.render_subject(recipient);
-    let rendered_body = template.render_body(recipient);
+    let rendered_body_html = template.render_body_html(recipient);
 
     let mut builder = Message::builder()
         .from(from.parse()?)
-        .reply_to(config.username.parse()?)
-        .to(recipient.email.parse()?)
+        .reply_to(config.sender_address().parse()?)
         .subject(rendered_subject);
+    for mailbox in recipient.to_mailboxes()? {
+        builder = builder.to(mailbox);
+    }
+    // Template-wide copies (e.g. an archive address) go with every recipient's own
+    for mailbox in parse_address_list(&recipient.cc)?
+        .into_iter()
+        .chain(parse_address_list(&template.cc)?)
+    {
+        builder = builder.cc(mailbox);
+    }
+    for mailbox in parse_address_list(&recipient.bcc)?
+        .into_iter()
+        .chain(parse_address_list(&template.bcc)?)
+    {
+        builder = builder.bcc(mailbox);
+    }
 
     // Add Message-ID header for anti-spam
     let msg_id = format!(
@@ -36,9 +271,9 @@ pub fn build_message(
     );
     builder = builder.message_id(Some(msg_id));
 
-    // Convert plain newlines to <br> so line breaks are preserved in the email.
-    // HTML tags from the formatting toolbar (bold, italic, underline) pass through as-is.
-    let rendered_body_html = rendered_body.replace('\n', "<br>");
+    for (name, value) in template.render_headers(recipient)? {
+        builder = builder.raw_header(HeaderValue::new(HeaderName::new_from_ascii(name)?, value));
+    }
 
     // Build the HTML body with a wrapper for proper email rendering
     let html_body = format!(
@@ -48,75 +283,502 @@ pub fn build_message(
         rendered_body_html
     );
 
-    // Build a plain-text fallback by stripping HTML tags
-    let plain_body = strip_html_tags(&rendered_body);
+    // The plain-text alternative is either written by the author or converted from the HTML
+    let plain_body = template.render_plain_body(recipient);
 
-    // Create an alternative part (plain + HTML) so email clients pick the best version
-    let alternative = MultiPart::alternative()
-        .singlepart(
-            SinglePart::builder()
-                .header(ContentType::TEXT_PLAIN)
-                .body(plain_body),
+    // Read every file up front: a message missing an attachment must not go out
+    let unresolved = template.unresolved_attachment_paths(recipient);
+    if !unresolved.is_empty() {
+        return Err(unresolved.join("; ").into());
+    }
+    let images = template
+        .referenced_images(&rendered_body_html)
+        .into_iter()
+        .map(|image| {
+            let path = image.file();
+            let bytes = std::fs::read(&path)
+                .map_err(|e| format!("inline image '{}' ({}): {}", image.cid, path.display(), e))?;
+            Ok((image.cid.clone(), content_type_for(&path, &bytes), bytes))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let attachments = template
+        .attachments_for(recipient)
+        .iter()
+        .map(|path| {
+            let bytes = std::fs::read(path)
+                .map_err(|e| format!("attachment {}: {}", path.display(), e))?;
+            let filename = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "attachment".to_string());
+            Ok((filename, content_type_for(path, &bytes), bytes))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    let total: u64 = images
+        .iter()
+        .chain(&attachments)
+        .map(|(_, _, bytes)| encoded_size(bytes.len() as u64))
+        .sum();
+    if let Some(limit) = config.attachment_limit()
+        && total > limit
+    {
+        return Err(format!(
+            "attachments are {} once encoded, over the {} limit",
+            format_size(total),
+            format_size(limit)
         )
-        .singlepart(
-            SinglePart::builder()
-                .header(ContentType::TEXT_HTML)
-                .body(html_body),
-        );
+        .into());
+    }
+
+    let html_part = SinglePart::builder()
+        .header(ContentType::TEXT_HTML)
+        .body(html_body);
 
-    if template.attachment_paths.is_empty() {
-        Ok(builder.multipart(alternative)?)
+    // Create an alternative part (plain + HTML) so email clients pick the best version
+    let alternative = MultiPart::alternative().singlepart(
+        SinglePart::builder()
+            .header(ContentType::TEXT_PLAIN)
+            .body(plain_body),
+    );
+
+    // Images referenced by cid: travel with the HTML in a multipart/related part
+    let alternative = if images.is_empty() {
+        alternative.singlepart(html_part)
+    } else {
+        let mut related = MultiPart::related().singlepart(html_part);
+        for (cid, content_type, bytes) in images {
+            related = related.singlepart(Attachment::new_inline(cid).body(bytes, content_type));
+        }
+        alternative.multipart(related)
+    };
+
+    let mut message = if attachments.is_empty() {
+        builder.multipart(alternative)?
     } else {
         // Wrap alternative + attachments in a mixed multipart
         let mut multipart = MultiPart::mixed().multipart(alternative);
+        for (filename, content_type, bytes) in attachments {
+            multipart = multipart.singlepart(Attachment::new(filename).body(bytes, content_type));
+        }
+        builder.multipart(multipart)?
+    };
+
+    // Signing has to come last: any header added afterwards breaks the signature
+    if let Some(dkim) = &config.dkim {
+        message.sign(&dkim_config(dkim)?);
+    }
+    Ok(message)
+}
 
-        for path in &template.attachment_paths {
-            if let Ok(file_bytes) = std::fs::read(path) {
-                let filename = path
-                    .file_name()
-                    .map(|n| n.to_string_lossy().to_string())
-                    .unwrap_or_else(|| "attachment".to_string());
+/// Headers covered by the DKIM signature. Ones a message doesn't have are
+/// still listed, so they can't be added in transit without breaking it.
+/// `Content-Type` is left out: lettre only writes it for multipart bodies
+/// when formatting, after signing, so verifiers would see a different list.
+const DKIM_SIGNED_HEADERS: &[&str] = &[
+    "From",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Subject",
+    "Date",
+    "Message-ID",
+    "MIME-Version",
+    "List-Unsubscribe",
+    "List-Unsubscribe-Post",
+];
 
-                let content_type = ContentType::parse("application/octet-stream")
-                    .unwrap_or(ContentType::TEXT_PLAIN);
+/// Loads the profile's DKIM key and builds the signing configuration.
+pub fn dkim_config(settings: &DkimSettings) -> Result<DkimConfig, String> {
+    let key_path = paths::settings_path(&settings.private_key_path);
+    let path = key_path.display();
+    let key = std::fs::read_to_string(&key_path)
+        .map_err(|e| format!("dkim private key {}: {}", path, e))?;
+    let algorithm = match settings.algorithm {
+        DkimAlgorithm::Rsa => DkimSigningAlgorithm::Rsa,
+        DkimAlgorithm::Ed25519 => DkimSigningAlgorithm::Ed25519,
+    };
+    let key = DkimSigningKey::new(key.trim(), algorithm).map_err(|e| {
+        if settings.algorithm == DkimAlgorithm::Rsa && key.contains("BEGIN PRIVATE KEY") {
+            format!(
+                "dkim private key {} is PKCS#8; convert it with `openssl rsa -in {} -traditional`",
+                path, path
+            )
+        } else {
+            format!("dkim private key {}: {}", path, e)
+        }
+    })?;
+    let headers = DKIM_SIGNED_HEADERS
+        .iter()
+        .map(|name| HeaderName::new_from_ascii_str(name))
+        .collect();
+    Ok(DkimConfig::new(
+        settings.selector.clone(),
+        settings.domain.clone(),
+        key,
+        headers,
+        // Relaxed survives relays that rewrap headers or trailing whitespace
+        DkimCanonicalization {
+            header: DkimCanonicalizationType::Relaxed,
+            body: DkimCanonicalizationType::Relaxed,
+        },
+    ))
+}
 
-                let attachment =
-                    Attachment::new(filename).body(file_bytes, content_type);
+/// Detects a file's content type from its magic bytes, falling back to the
+/// extension. Container formats (zip, OLE) defer to the extension, which
+/// tells e.g. a .docx from a plain zip.
+fn content_type_for(path: &Path, bytes: &[u8]) -> ContentType {
+    let by_extension = mime_guess::from_path(path).first();
+    let mime = match infer::get(bytes).map(|kind| kind.mime_type()) {
+        Some("application/zip" | "application/x-ole-storage") | None => by_extension
+            .map(|m| m.essence_str().to_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string()),
+        Some(detected) => detected.to_string(),
+    };
+    ContentType::parse(&mime)
+        .or_else(|_| ContentType::parse("application/octet-stream"))
+        .unwrap_or(ContentType::TEXT_PLAIN)
+}
 
-                multipart = multipart.singlepart(attachment);
-            }
+/// Size of `len` bytes once base64-encoded in 76-column lines, as attachments are sent.
+pub fn encoded_size(len: u64) -> u64 {
+    let base64 = len.div_ceil(3) * 4;
+    base64 + base64.div_ceil(76) * 2
+}
+
+/// Estimated encoded size of the recipient's attachments and the inline
+/// images the body refers to. Files that can't be read count as empty.
+pub fn estimated_attachments_size(template: &EmailTemplate, recipient: &Recipient) -> u64 {
+    let images = template.referenced_images(&template.body);
+    template
+        .attachments_for(recipient)
+        .into_iter()
+        .chain(images.into_iter().map(InlineImage::file))
+        .map(|path| encoded_size(std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)))
+        .sum()
+}
+
+pub fn format_size(bytes: u64) -> String {
+    const KB: f64 = 1024.0;
+    let b = bytes as f64;
+    if b >= KB * KB {
+        format!("{:.1} MB", b / (KB * KB))
+    } else if b >= KB {
+        format!("{:.0} KB", b / KB)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Builds the transport selected by `config.transport`.
+pub fn create_transport(
+    config: &SmtpConfig,
+) -> Result<Box<dyn MailTransport>, Box<dyn std::error::Error>> {
+    let transport: Box<dyn MailTransport> = match &config.transport {
+        TransportKind::Smtp if config.oauth2.is_some() => Box::new(OAuthSmtpTransport::new(config)?),
+        TransportKind::Smtp => Box::new(create_smtp_transport(config)?),
+        TransportKind::File { path } => {
+            let path = paths::settings_path(path);
+            std::fs::create_dir_all(&path)?;
+            Box::new(FileTransport::new(path))
         }
+        TransportKind::Sendmail { command } => Box::new(SendmailTransport {
+            command: command.as_deref().unwrap_or("sendmail").into(),
+        }),
+        TransportKind::Memory => Box::new(MemoryTransport::default()),
+    };
+    Ok(transport)
+}
+
+pub fn create_smtp_transport(
+    config: &SmtpConfig,
+) -> Result<SmtpTransport, Box<dyn std::error::Error>> {
+    let builder = smtp_builder(config)?;
+    let builder = if let Some(password) = config.password()? {
+        let creds = Credentials::new(config.username.clone(), password.expose().to_string());
+        builder.credentials(creds)
+    } else {
+        builder
+    };
+
+    Ok(builder.build())
+}
+
+fn smtp_builder(config: &SmtpConfig) -> Result<SmtpTransportBuilder, Box<dyn std::error::Error>> {
+    let builder = match config.tls {
+        TlsMode::Implicit => SmtpTransport::relay(&config.host)?,
+        TlsMode::Starttls => SmtpTransport::starttls_relay(&config.host)?,
+        TlsMode::Opportunistic => SmtpTransport::builder_dangerous(&config.host)
+            .tls(Tls::Opportunistic(TlsParameters::new(config.host.clone())?)),
+        TlsMode::None => SmtpTransport::builder_dangerous(&config.host),
+    };
+    Ok(builder.port(config.port()))
+}
+
+/// Authenticates with XOAUTH2, passing the access token where the password would go.
+fn with_oauth_token(
+    builder: SmtpTransportBuilder,
+    config: &SmtpConfig,
+    token: &OAuthToken,
+) -> SmtpTransportBuilder {
+    builder
+        .credentials(Credentials::new(config.username.clone(), token.access_token.clone()))
+        .authentication(vec![Mechanism::Xoauth2])
+}
+
+/// SMTP with an OAuth2 profile. Access tokens last about an hour, so before
+/// each message the token is checked and, when close to expiring, refreshed
+/// and the connection pool rebuilt with it.
+struct OAuthSmtpTransport {
+    config: SmtpConfig,
+    current: Mutex<(SmtpTransport, OAuthToken)>,
+}
 
-        Ok(builder.multipart(multipart)?)
+impl OAuthSmtpTransport {
+    fn new(config: &SmtpConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        let token = oauth::current_token(config)?;
+        let transport = with_oauth_token(smtp_builder(config)?, config, &token).build();
+        Ok(Self {
+            config: config.clone(),
+            current: Mutex::new((transport, token)),
+        })
     }
 }
 
-pub fn create_transport(config: &SmtpConfig) -> Result<SmtpTransport, Box<dyn std::error::Error>> {
-    let creds = Credentials::new(config.username.clone(), config.password.clone());
+impl MailTransport for OAuthSmtpTransport {
+    fn deliver(&self, message: &Message) -> Result<(), TransportError> {
+        let mut current = self.current.lock().map_err(|_| "transport is poisoned")?;
+        if current.1.is_expiring() {
+            let token = oauth::current_token(&self.config)?;
+            let builder = smtp_builder(&self.config).map_err(|e| e.to_string())?;
+            *current = (with_oauth_token(builder, &self.config, &token).build(), token);
+        }
+        current.0.send(message)?;
+        Ok(())
+    }
+}
 
-    let transport = SmtpTransport::relay(&config.host)?
-        // .port(config.port)
-        .credentials(creds)
-        .build();
+/// One stage of a connection test, reported as soon as it finishes.
+#[derive(Debug, Clone)]
+pub struct TestStep {
+    pub stage: &'static str,
+    /// What the stage found, or why it failed.
+    pub result: Result<String, String>,
+}
 
-    Ok(transport)
+/// Checks that the profile can reach and log in to its SMTP server, one
+/// stage at a time: credentials, DNS lookup, connect (and the TLS handshake
+/// for implicit TLS), EHLO, STARTTLS and AUTH. Each stage is reported on
+/// `progress`, and the test stops at the first failure. No mail is sent.
+/// Blocks for up to a few timeouts, so run it off the UI thread.
+pub fn test_connection(config: &SmtpConfig, progress: &Sender<TestStep>) {
+    let report = |stage: &'static str, result: Result<String, String>| {
+        let ok = result.is_ok();
+        let _ = progress.send(TestStep { stage, result });
+        ok
+    };
+
+    if config.transport != TransportKind::Smtp {
+        report("Transport", Ok("this profile doesn't send over SMTP; nothing to test".to_string()));
+        return;
+    }
+
+    let credentials = if config.oauth2.is_some() {
+        oauth::current_token(config)
+            .map(|token| {
+                Some((
+                    Credentials::new(config.username.clone(), token.access_token),
+                    vec![Mechanism::Xoauth2],
+                ))
+            })
+            .map_err(String::from)
+    } else {
+        config.password().map(|password| {
+            password.map(|p| {
+                (
+                    Credentials::new(config.username.clone(), p.expose().to_string()),
+                    DEFAULT_MECHANISMS.to_vec(),
+                )
+            })
+        })
+    };
+    let credentials = match credentials {
+        Ok(credentials) => {
+            let found = match (&credentials, config.oauth2.is_some()) {
+                (None, _) => "none configured; AUTH will be skipped",
+                (Some(_), true) => "OAuth2 access token ready",
+                (Some(_), false) => "password found",
+            };
+            report("Credentials", Ok(found.to_string()));
+            credentials
+        }
+        Err(e) => {
+            report("Credentials", Err(e));
+            return;
+        }
+    };
+
+    let port = config.port();
+    let addresses: Vec<std::net::SocketAddr> =
+        match std::net::ToSocketAddrs::to_socket_addrs(&(config.host.as_str(), port)) {
+            Ok(addresses) => addresses.collect(),
+            Err(e) => {
+                report("DNS lookup", Err(format!("{}: {}", config.host, e)));
+                return;
+            }
+        };
+    let listed: Vec<String> = addresses.iter().map(|a| a.ip().to_string()).collect();
+    if !report("DNS lookup", Ok(format!("{} → {}", config.host, listed.join(", ")))) {
+        return;
+    }
+
+    let tls = match TlsParameters::new(config.host.clone()) {
+        Ok(tls) => tls,
+        Err(e) => {
+            report("TLS setup", Err(e.to_string()));
+            return;
+        }
+    };
+    let hello = ClientId::default();
+    let timeout = Some(Duration::from_secs(15));
+    let implicit = (config.tls == TlsMode::Implicit).then_some(&tls);
+    let mut connection = match SmtpConnection::connect(&addresses[..], timeout, &hello, implicit, None) {
+        Ok(connection) => connection,
+        Err(e) => {
+            // connect() covers TCP, the implicit TLS handshake, the greeting and EHLO
+            let stage = if e.is_tls() {
+                "TLS handshake"
+            } else if e.is_response() || e.is_transient() || e.is_permanent() {
+                "EHLO"
+            } else {
+                "Connect"
+            };
+            report(stage, Err(e.to_string()));
+            return;
+        }
+    };
+    report("Connect", Ok(format!("{}:{}", config.host, port)));
+    if implicit.is_some() {
+        report("TLS handshake", Ok("encrypted".to_string()));
+    }
+    report("EHLO", Ok(describe_server(&connection)));
+
+    let starttls = match config.tls {
+        TlsMode::Starttls => true,
+        TlsMode::Opportunistic => connection.can_starttls(),
+        TlsMode::Implicit | TlsMode::None => false,
+    };
+    if starttls {
+        let result = connection
+            .starttls(&tls, &hello)
+            .map(|()| "encrypted".to_string())
+            .map_err(|e| e.to_string());
+        if !report("STARTTLS", result) {
+            connection.abort();
+            return;
+        }
+    } else if config.tls == TlsMode::Opportunistic {
+        report("STARTTLS", Ok("not offered; continuing unencrypted".to_string()));
+    }
+
+    if let Some((credentials, mechanisms)) = credentials {
+        let mechanism = connection.server_info().get_auth_mechanism(&mechanisms);
+        let result = connection
+            .auth(&mechanisms, &credentials)
+            .map(|_| match mechanism {
+                Some(mechanism) => format!("logged in as {} ({})", config.username, mechanism),
+                None => format!("logged in as {}", config.username),
+            })
+            .map_err(|e| e.to_string());
+        if !report("AUTH", result) {
+            connection.abort();
+            return;
+        }
+    }
+    let _ = connection.quit();
+}
+
+/// The server's name and the EHLO extensions that matter for logging in.
+fn describe_server(connection: &SmtpConnection) -> String {
+    let info = connection.server_info();
+    let mut offers = Vec::new();
+    if info.supports_feature(Extension::StartTls) {
+        offers.push("STARTTLS".to_string());
+    }
+    let mechanisms: Vec<String> = [Mechanism::Plain, Mechanism::Login, Mechanism::Xoauth2]
+        .into_iter()
+        .filter(|m| info.supports_auth_mechanism(*m))
+        .map(|m| m.to_string())
+        .collect();
+    if !mechanisms.is_empty() {
+        offers.push(format!("AUTH {}", mechanisms.join(" ")));
+    }
+    if offers.is_empty() {
+        info.name().to_string()
+    } else {
+        format!("{} offers {}", info.name(), offers.join(", "))
+    }
 }
 
+/// Sends the template to the recipient at `index` on a background thread,
+/// reporting `Sent` or `Failed` and then `Done`. Nothing is retried.
 pub fn send_single(
+    config: SmtpConfig,
+    template: EmailTemplate,
+    index: usize,
+    progress_tx: Sender<SendProgress>,
+) {
+    std::thread::spawn(move || {
+        if let Some(recipient) = template.recipients.get(index) {
+            let email = recipient.email.clone();
+            let _ = progress_tx.send(match deliver_one(&config, &template, index, &progress_tx) {
+                Ok(message_id) => SendProgress::Sent { index, email, message_id },
+                Err((error, kind)) => SendProgress::Failed { index, email, error, kind },
+            });
+        }
+        let _ = progress_tx.send(SendProgress::Done);
+    });
+}
+
+/// Builds and delivers one message, returning its Message-ID.
+fn deliver_one(
     config: &SmtpConfig,
     template: &EmailTemplate,
-    recipient: &Recipient,
-) -> Result<(), String> {
-    let transport = create_transport(config).map_err(|e| e.to_string())?;
-    let message = build_message(config, template, recipient).map_err(|e| e.to_string())?;
-    transport.send(&message).map_err(|e| format!("{:?}", e))?;
-    Ok(())
+    index: usize,
+    progress_tx: &Sender<SendProgress>,
+) -> Result<String, (String, FailureKind)> {
+    let recipient = &template.recipients[index];
+    let transport = create_transport(config).map_err(|e| {
+        (format!("Failed to create transport: {}", e), FailureKind::classify(e.as_ref()))
+    })?;
+    let message = build_message(config, template, recipient)
+        .map_err(|e| (e.to_string(), FailureKind::Permanent))?;
+    transport
+        .deliver(&message)
+        .map_err(|e| (e.to_string(), FailureKind::classify(e.as_ref())))?;
+    if let Some(summary) = transport.summary(&message) {
+        let _ = progress_tx.send(SendProgress::Recorded { index, summary });
+    }
+    Ok(message_id(&message))
 }
 
+pub fn message_id(message: &Message) -> String {
+    message
+        .headers()
+        .get_raw("Message-ID")
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Sends the template to the recipients at `targets` (indices into
+/// `template.recipients`) on a background thread.
 pub fn send_bulk(
     config: SmtpConfig,
     template: EmailTemplate,
+    targets: Vec<usize>,
     progress_tx: Sender<SendProgress>,
+    control_rx: Receiver<SendControl>,
 ) {
     std::thread::spawn(move || {
         let transport = match create_transport(&config) {
@@ -126,50 +788,259 @@ pub fn send_bulk(
                     index: 0,
                     email: "N/A".to_string(),
                     error: format!("Failed to create transport: {}", e),
+                    kind: FailureKind::Permanent,
                 });
                 let _ = progress_tx.send(SendProgress::Done);
                 return;
             }
         };
 
-        let delay = std::time::Duration::from_millis(config.send_delay_ms);
+        run_bulk(
+            transport.as_ref(),
+            &config,
+            &template,
+            &targets,
+            &progress_tx,
+            &control_rx,
+        );
+    });
+}
+
+/// Sends the template to the recipients at `targets` through `transport` on
+/// the current thread, reporting each result and finishing with `SendProgress::Done`
+/// (or `SendProgress::Cancelled`).
+pub fn run_bulk(
+    transport: &dyn MailTransport,
+    config: &SmtpConfig,
+    template: &EmailTemplate,
+    targets: &[usize],
+    progress_tx: &Sender<SendProgress>,
+    control_rx: &Receiver<SendControl>,
+) {
+    let delay = Duration::from_millis(config.send_delay_ms);
 
-        for (i, recipient) in template.recipients.iter().enumerate() {
-            match build_message(&config, &template, recipient) {
-                Ok(message) => match transport.send(&message) {
-                    Ok(_) => {
-                        let _ = progress_tx.send(SendProgress::Sent {
-                            index: i,
-                            email: recipient.email.clone(),
-                        });
+    for (n, &i) in targets.iter().enumerate() {
+        // Throttle to avoid spam filters
+        let wait = if n == 0 { Duration::ZERO } else { delay };
+        if !wait_with_control(control_rx, progress_tx, wait) {
+            let _ = progress_tx.send(SendProgress::Cancelled {
+                remaining: targets.len() - n,
+            });
+            return;
+        }
+
+        let Some(recipient) = template.recipients.get(i) else {
+            continue;
+        };
+        let message = match build_message(config, template, recipient) {
+            Ok(message) => message,
+            Err(e) => {
+                let _ = progress_tx.send(SendProgress::Failed {
+                    index: i,
+                    email: recipient.email.clone(),
+                    error: e.to_string(),
+                    kind: FailureKind::Permanent,
+                });
+                continue;
+            }
+        };
+
+        let mut attempt = 0;
+        loop {
+            match transport.deliver(&message) {
+                Ok(()) => {
+                    if let Some(summary) = transport.summary(&message) {
+                        let _ = progress_tx.send(SendProgress::Recorded { index: i, summary });
                     }
-                    Err(e) => {
-                        let _ = progress_tx.send(SendProgress::Failed {
+                    let _ = progress_tx.send(SendProgress::Sent {
+                        index: i,
+                        email: recipient.email.clone(),
+                        message_id: message_id(&message),
+                    });
+                    break;
+                }
+                Err(e) => {
+                    let kind = FailureKind::classify(e.as_ref());
+                    if kind == FailureKind::Transient && attempt < config.max_retries {
+                        attempt += 1;
+                        let delay = config.retry_delay(attempt);
+                        let _ = progress_tx.send(SendProgress::Retrying {
                             index: i,
                             email: recipient.email.clone(),
-                            error: format!("{:?}", e),
+                            error: e.to_string(),
+                            attempt,
+                            delay,
                         });
+                        if !wait_with_control(control_rx, progress_tx, delay) {
+                            let _ = progress_tx.send(SendProgress::Cancelled {
+                                remaining: targets.len() - n,
+                            });
+                            return;
+                        }
+                        continue;
                     }
-                },
-                Err(e) => {
                     let _ = progress_tx.send(SendProgress::Failed {
                         index: i,
                         email: recipient.email.clone(),
                         error: e.to_string(),
+                        kind,
                     });
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = progress_tx.send(SendProgress::Done);
+}
+
+/// Waits for `duration` while handling control commands. A pause holds the
+/// wait open until `Resume` or `Cancel` arrives. Returns `false` on cancel.
+fn wait_with_control(
+    control_rx: &Receiver<SendControl>,
+    progress_tx: &Sender<SendProgress>,
+    duration: Duration,
+) -> bool {
+    let deadline = Instant::now() + duration;
+    let mut paused = false;
+    loop {
+        let command = if paused {
+            match control_rx.recv() {
+                Ok(command) => command,
+                // Nobody is left to resume us.
+                Err(_) => return false,
+            }
+        } else {
+            match control_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(command) => command,
+                Err(RecvTimeoutError::Timeout) => return true,
+                Err(RecvTimeoutError::Disconnected) => {
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    return true;
                 }
             }
+        };
 
-            // Throttle to avoid spam filters
-            if i < template.recipients.len() - 1 {
-                std::thread::sleep(delay);
+        match command {
+            SendControl::Pause if !paused => {
+                paused = true;
+                let _ = progress_tx.send(SendProgress::Paused);
+            }
+            SendControl::Resume if paused => {
+                paused = false;
+                let _ = progress_tx.send(SendProgress::Resumed);
             }
+            SendControl::Cancel => return false,
+            _ => {}
         }
+    }
+}
 
-        let _ = progress_tx.send(SendProgress::Done);
+/// Summary of one recipient's message in a dry run.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunEntry {
+    pub index: usize,
+    pub recipient: String,
+    pub subject: String,
+    /// Name of the `.eml` file, or `None` if the message could not be built.
+    pub file: Option<String>,
+    pub size_bytes: usize,
+    pub attachments: Vec<String>,
+    pub unresolved_placeholders: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Contents of `manifest.json` written next to the `.eml` files of a dry run.
+#[derive(Debug, Clone, Serialize)]
+pub struct DryRunManifest {
+    pub template: String,
+    pub sender: String,
+    pub generated_at: u64,
+    pub messages: Vec<DryRunEntry>,
+}
+
+/// Runs [`write_dry_run`] on a background thread, reporting `Written` for
+/// each message and finishing with `DryRunDone`.
+pub fn dry_run(
+    config: SmtpConfig,
+    template: EmailTemplate,
+    out_dir: PathBuf,
+    progress_tx: Sender<SendProgress>,
+) {
+    std::thread::spawn(move || {
+        let result = write_dry_run(&config, &template, &out_dir, &progress_tx)
+            .map_err(|e| e.to_string());
+        let _ = progress_tx.send(SendProgress::DryRunDone { out_dir, result });
     });
 }
 
+/// Renders every recipient's message through `build_message` and writes it to
+/// `out_dir` as an `.eml` file, plus a `manifest.json` describing each one.
+/// Nothing is sent.
+pub fn write_dry_run(
+    config: &SmtpConfig,
+    template: &EmailTemplate,
+    out_dir: &Path,
+    progress_tx: &Sender<SendProgress>,
+) -> Result<DryRunManifest, Box<dyn std::error::Error>> {
+    std::fs::create_dir_all(out_dir)?;
+
+    let mut messages = Vec::with_capacity(template.recipients.len());
+    for (i, recipient) in template.recipients.iter().enumerate() {
+        let mut entry = DryRunEntry {
+            index: i,
+            recipient: recipient.email.clone(),
+            subject: template.render_subject(recipient),
+            file: None,
+            size_bytes: 0,
+            attachments: template
+                .attachments_for(recipient)
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect(),
+            unresolved_placeholders: template.unresolved_placeholders(recipient),
+            error: None,
+        };
+
+        match build_message(config, template, recipient) {
+            Ok(message) => {
+                let bytes = message.formatted();
+                let file = format!("{:03}-{}.eml", i + 1, sanitize_file_name(&recipient.email));
+                std::fs::write(out_dir.join(&file), &bytes)?;
+                entry.file = Some(file);
+                entry.size_bytes = bytes.len();
+            }
+            Err(e) => entry.error = Some(e.to_string()),
+        }
+        messages.push(entry);
+        let _ = progress_tx.send(SendProgress::Written);
+    }
+
+    let manifest = DryRunManifest {
+        template: template.name.clone(),
+        sender: config.name.clone(),
+        generated_at: chrono_timestamp(),
+        messages,
+    };
+    std::fs::write(
+        out_dir.join("manifest.json"),
+        serde_json::to_string_pretty(&manifest)?,
+    )?;
+    Ok(manifest)
+}
+
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '@' | '.' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 fn chrono_timestamp() -> u64 {
     std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
@@ -177,18 +1048,400 @@ fn chrono_timestamp() -> u64 {
         .as_secs()
 }
 
-/// Simple HTML tag stripper for generating a plain-text fallback.
-fn strip_html_tags(html: &str) -> String {
-    let mut result = String::with_capacity(html.len());
-    let mut inside_tag = false;
-    for c in html.chars() {
-        match c {
-            '<' => inside_tag = true,
-            '>' => inside_tag = false,
-            _ if !inside_tag => result.push(c),
-            _ => {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, VecDeque};
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    /// Records every delivered message instead of sending it. Failures queued
+    /// with `fail_next` are returned by the next deliveries, in order.
+    #[derive(Clone, Default)]
+    struct ScriptedTransport {
+        messages: Arc<Mutex<Vec<Message>>>,
+        failures: Arc<Mutex<VecDeque<TransportError>>>,
+    }
+
+    impl ScriptedTransport {
+        fn fail_next(&self, error: impl Into<TransportError>) {
+            self.failures.lock().unwrap().push_back(error.into());
+        }
+
+        fn messages(&self) -> Vec<Message> {
+            self.messages.lock().unwrap().clone()
         }
     }
-    result
-}
 
+    impl MailTransport for ScriptedTransport {
+        fn deliver(&self, message: &Message) -> Result<(), TransportError> {
+            if let Some(error) = self.failures.lock().unwrap().pop_front() {
+                return Err(error);
+            }
+            self.messages.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    fn config() -> SmtpConfig {
+        let mut config = SmtpConfig::new(
+            "Test".to_string(),
+            "localhost".to_string(),
+            "sender@example.com".to_string(),
+            "Sender".to_string(),
+        );
+        config.send_delay_ms = 0;
+        config.retry_delay_ms = 0;
+        config.retry_max_delay_ms = 0;
+        config
+    }
+
+    fn template(emails: &[&str]) -> EmailTemplate {
+        let mut template = EmailTemplate::new("Test".to_string());
+        template.subject = "Hello {name}".to_string();
+        template.body = "<p>Hi {name}</p>".to_string();
+        template.recipients = emails
+            .iter()
+            .map(|email| Recipient {
+                email: email.to_string(),
+                args: HashMap::from([("name".to_string(), "Jane".to_string())]),
+                ..Default::default()
+            })
+            .collect();
+        template
+    }
+
+    fn run(transport: &dyn MailTransport, config: &SmtpConfig, template: &EmailTemplate) -> Vec<SendProgress> {
+        let (progress_tx, progress_rx) = mpsc::channel();
+        let (_control_tx, control_rx) = mpsc::channel();
+        let targets: Vec<usize> = (0..template.recipients.len()).collect();
+        run_bulk(transport, config, template, &targets, &progress_tx, &control_rx);
+        drop(progress_tx);
+        progress_rx.into_iter().collect()
+    }
+
+    fn transient() -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::ConnectionReset, "connection reset")
+    }
+
+    #[test]
+    fn sends_to_every_recipient() {
+        let transport = ScriptedTransport::default();
+        let template = template(&["a@example.com", "b@example.com"]);
+        let progress = run(&transport, &config(), &template);
+
+        let messages = transport.messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(progress.len(), 3);
+        for (i, (event, message)) in progress.iter().zip(&messages).enumerate() {
+            let SendProgress::Sent { index, email, message_id: id } = event else {
+                panic!("expected Sent, got {:?}", event);
+            };
+            assert_eq!(*index, i);
+            assert_eq!(email, &template.recipients[i].email);
+            assert_eq!(id, &message_id(message));
+            assert_eq!(message.envelope().to()[0].to_string(), *email);
+            assert_eq!(message.headers().get_raw("Subject"), Some("Hello Jane"));
+        }
+        assert!(matches!(progress[2], SendProgress::Done));
+    }
+
+    #[test]
+    fn memory_transport_lists_each_message() {
+        let mut config = config();
+        config.transport = TransportKind::Memory;
+        let transport = create_transport(&config).unwrap();
+        let progress = run(transport.as_ref(), &config, &template(&["a@example.com", "b@example.com"]));
+
+        assert_eq!(progress.len(), 5);
+        for (n, email) in ["a@example.com", "b@example.com"].iter().enumerate() {
+            let SendProgress::Recorded { index, summary } = &progress[n * 2] else {
+                panic!("expected Recorded, got {:?}", progress[n * 2]);
+            };
+            assert_eq!(*index, n);
+            let expected = format!("Kept message {} in memory: to {}, subject \"Hello Jane\", ", n + 1, email);
+            assert!(summary.starts_with(&expected), "{}", summary);
+            assert!(matches!(&progress[n * 2 + 1], SendProgress::Sent { index, .. } if *index == n));
+        }
+        assert!(matches!(progress[4], SendProgress::Done));
+    }
+
+    #[test]
+    fn inline_images_travel_in_a_related_part() {
+        let dir = std::env::temp_dir().join(format!("email-senderr-inline-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let logo = dir.join("logo.png");
+        std::fs::write(&logo, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+
+        let transport = ScriptedTransport::default();
+        let mut template = template(&["a@example.com"]);
+        template.body = "<p>Hi {name}</p><img src=\"cid:logo.png\">".to_string();
+        template.inline_images = vec![InlineImage { cid: "logo.png".to_string(), path: logo }];
+        let progress = run(&transport, &config(), &template);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(progress[0], SendProgress::Sent { index: 0, .. }), "{:?}", progress[0]);
+        let raw = String::from_utf8(transport.messages()[0].formatted()).unwrap();
+        let related = raw.find("Content-Type: multipart/related").expect("no multipart/related part");
+        let html = raw.find("cid:logo.png").expect("no cid reference in the HTML");
+        let image = raw.find("Content-ID: <logo.png>").expect("no Content-ID for the image");
+        assert!(related < html && html < image, "{}", raw);
+        assert!(raw[image..].contains("Content-Type: image/png"), "{}", raw);
+        assert!(raw[image..].contains("Content-Disposition: inline"), "{}", raw);
+    }
+
+    #[test]
+    fn attachments_over_the_limit_fail_instead_of_sending() {
+        let dir = std::env::temp_dir().join(format!("email-senderr-limit-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let big = dir.join("big.bin");
+        std::fs::write(&big, vec![0u8; 800 * 1024]).unwrap();
+
+        let transport = ScriptedTransport::default();
+        let mut config = config();
+        config.attachment_limit_mb = 1;
+        let mut template = template(&["a@example.com", "b@example.com"]);
+        template.recipients[0].attachments = vec![big];
+        // 800 KB is about 1.06 MB once base64-encoded
+        let estimate = estimated_attachments_size(&template, &template.recipients[0]);
+        assert!(estimate > config.attachment_limit().unwrap());
+        assert_eq!(estimated_attachments_size(&template, &template.recipients[1]), 0);
+        let progress = run(&transport, &config, &template);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let SendProgress::Failed { index: 0, error, kind, .. } = &progress[0] else {
+            panic!("expected Failed, got {:?}", progress[0]);
+        };
+        assert_eq!(*kind, FailureKind::Permanent);
+        assert_eq!(error, &format!("attachments are {} once encoded, over the 1.0 MB limit", format_size(estimate)));
+        assert!(matches!(progress[1], SendProgress::Sent { index: 1, .. }));
+        let messages = transport.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].envelope().to()[0].to_string(), "b@example.com");
+    }
+
+    #[test]
+    fn retries_temporary_failures() {
+        let transport = ScriptedTransport::default();
+        transport.fail_next(transient());
+        transport.fail_next(transient());
+        let progress = run(&transport, &config(), &template(&["a@example.com"]));
+
+        assert_eq!(transport.messages().len(), 1);
+        assert!(matches!(progress[0], SendProgress::Retrying { index: 0, attempt: 1, .. }));
+        assert!(matches!(progress[1], SendProgress::Retrying { index: 0, attempt: 2, .. }));
+        assert!(matches!(progress[2], SendProgress::Sent { index: 0, .. }));
+        assert!(matches!(progress[3], SendProgress::Done));
+    }
+
+    #[test]
+    fn gives_up_after_max_retries_and_moves_on() {
+        let transport = ScriptedTransport::default();
+        let mut config = config();
+        config.max_retries = 1;
+        transport.fail_next(transient());
+        transport.fail_next(transient());
+        let progress = run(&transport, &config, &template(&["a@example.com", "b@example.com"]));
+
+        assert!(matches!(progress[0], SendProgress::Retrying { index: 0, attempt: 1, .. }));
+        assert!(matches!(
+            progress[1],
+            SendProgress::Failed { index: 0, kind: FailureKind::Transient, .. }
+        ));
+        assert!(matches!(progress[2], SendProgress::Sent { index: 1, .. }));
+        assert!(matches!(progress[3], SendProgress::Done));
+        assert_eq!(transport.messages().len(), 1);
+    }
+
+    #[test]
+    fn failures_only_affect_their_recipient() {
+        let transport = ScriptedTransport::default();
+        let template = template(&["a@example.com", "not an address", "c@example.com", "d@example.com"]);
+        // Recipient 0 is rejected by the server and not retried; recipient 1
+        // can't be built at all. Neither stops the rest of the run.
+        transport.fail_next("550 mailbox unavailable");
+        let progress = run(&transport, &config(), &template);
+
+        assert!(matches!(
+            progress[0],
+            SendProgress::Failed { index: 0, kind: FailureKind::Permanent, .. }
+        ));
+        assert!(matches!(
+            progress[1],
+            SendProgress::Failed { index: 1, kind: FailureKind::Permanent, .. }
+        ));
+        assert!(matches!(progress[2], SendProgress::Sent { index: 2, .. }));
+        assert!(matches!(progress[3], SendProgress::Sent { index: 3, .. }));
+        assert!(matches!(progress[4], SendProgress::Done));
+        assert_eq!(transport.messages().len(), 2);
+    }
+
+    #[test]
+    fn dry_run_reports_each_message_from_its_thread() {
+        let dir = std::env::temp_dir().join(format!("email-senderr-dry-run-{}", uuid::Uuid::new_v4()));
+        let (progress_tx, progress_rx) = mpsc::channel();
+        dry_run(config(), template(&["a@example.com", "b@example.com"]), dir.clone(), progress_tx);
+        let progress: Vec<SendProgress> = progress_rx.into_iter().collect();
+
+        let [SendProgress::Written, SendProgress::Written, SendProgress::DryRunDone { out_dir, result }] =
+            progress.as_slice()
+        else {
+            panic!("unexpected progress {:?}", progress);
+        };
+        let manifest = result.as_ref().unwrap();
+        assert_eq!(out_dir, &dir);
+        assert_eq!(manifest.messages.len(), 2);
+        assert!(dir.join("manifest.json").is_file());
+        assert!(dir.join("001-a@example.com.eml").is_file());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn token_refresh_failures_are_retried_only_when_temporary() {
+        let transport = ScriptedTransport::default();
+        transport.fail_next(TokenError::Unavailable("token endpoint returned 503".to_string()));
+        transport.fail_next(TokenError::Failed("invalid_grant: Token has been revoked".to_string()));
+        let progress = run(&transport, &config(), &template(&["a@example.com"]));
+
+        assert!(matches!(progress[0], SendProgress::Retrying { index: 0, attempt: 1, .. }));
+        assert!(matches!(
+            progress[1],
+            SendProgress::Failed { index: 0, kind: FailureKind::Permanent, .. }
+        ));
+        assert!(matches!(progress[2], SendProgress::Done));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sendmail_failures_are_temporary_only_for_tempfail_or_no_binary() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("email-senderr-sendmail-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let script = |name: &str, status: i32| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\ncat >/dev/null\necho {} >&2\nexit {}\n", name, status)).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let template = template(&["a@example.com"]);
+        let message = build_message(&config(), &template, &template.recipients[0]).unwrap();
+        let send = |command: PathBuf| {
+            SendmailTransport { command: command.into_os_string() }
+                .deliver(&message)
+                .unwrap_err()
+        };
+
+        let tempfail = send(script("queue-full", 75));
+        assert_eq!(FailureKind::classify(tempfail.as_ref()), FailureKind::Transient);
+        assert_eq!(tempfail.to_string(), "sendmail exited with status 75: queue-full");
+        let missing = send(dir.join("no-such-sendmail"));
+        assert_eq!(FailureKind::classify(missing.as_ref()), FailureKind::Transient);
+        let rejected = send(script("unknown-user", 67));
+        assert_eq!(FailureKind::classify(rejected.as_ref()), FailureKind::Permanent);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Relaxed header canonicalization (RFC 6376 3.4.2) of one unfolded header.
+    fn relaxed_header(name: &str, value: &str) -> String {
+        let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        format!("{}:{}", name.trim().to_ascii_lowercase(), value)
+    }
+
+    /// Relaxed body canonicalization (RFC 6376 3.4.4).
+    fn relaxed_body(body: &str) -> String {
+        let mut lines: Vec<String> = body
+            .split("\r\n")
+            .map(|line| line.split([' ', '\t']).filter(|w| !w.is_empty()).collect::<Vec<_>>().join(" "))
+            .collect();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines.iter().map(|line| format!("{}\r\n", line)).collect()
+    }
+
+    #[test]
+    fn dkim_signature_verifies() {
+        use base64::Engine;
+        use base64::engine::general_purpose::STANDARD;
+        use ed25519_dalek::{Signature, SigningKey, Verifier};
+        use sha2::{Digest, Sha256};
+
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let key_path = std::env::temp_dir().join(format!("email-senderr-dkim-{}.key", uuid::Uuid::new_v4()));
+        std::fs::write(&key_path, STANDARD.encode(signing_key.to_bytes())).unwrap();
+        let mut config = config();
+        config.dkim = Some(DkimSettings {
+            selector: "mail".to_string(),
+            domain: "example.com".to_string(),
+            private_key_path: key_path.clone(),
+            algorithm: DkimAlgorithm::Ed25519,
+        });
+        let mut template = template(&["a@example.com"]);
+        template.unsubscribe.url = "https://example.com/unsubscribe".to_string();
+        template.unsubscribe.one_click = true;
+
+        let message = build_message(&config, &template, &template.recipients[0]).unwrap();
+        std::fs::remove_file(&key_path).unwrap();
+        let formatted = String::from_utf8(message.formatted()).unwrap();
+        let (head, body) = formatted.split_once("\r\n\r\n").unwrap();
+        let unfolded = head.replace("\r\n ", " ").replace("\r\n\t", " ");
+        let headers: Vec<(&str, &str)> = unfolded
+            .split("\r\n")
+            .map(|line| line.split_once(':').unwrap())
+            .collect();
+        let signature_header = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("DKIM-Signature"))
+            .unwrap()
+            .1;
+        let tags: HashMap<&str, &str> = signature_header
+            .split(';')
+            .filter_map(|tag| tag.split_once('='))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        assert_eq!(tags["a"], "ed25519-sha256");
+        assert_eq!(tags["c"], "relaxed/relaxed");
+        assert_eq!(tags["d"], "example.com");
+        assert_eq!(tags["s"], "mail");
+
+        let body_hash = STANDARD.encode(Sha256::digest(relaxed_body(body)));
+        assert_eq!(tags["bh"].split_whitespace().collect::<String>(), body_hash);
+
+        let signed: Vec<&str> = tags["h"].split(':').map(str::trim).collect();
+        assert_eq!(
+            signed,
+            DKIM_SIGNED_HEADERS.iter().map(|h| h.to_ascii_lowercase()).collect::<Vec<_>>()
+        );
+
+        // Signed headers that are present, then the signature header with b= empty
+        let mut data = String::new();
+        for name in &signed {
+            if let Some((name, value)) = headers.iter().rev().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+                data.push_str(&relaxed_header(name, value));
+                data.push_str("\r\n");
+            }
+        }
+        let unsigned = signature_header
+            .split(';')
+            .map(|tag| match tag.split_once('=') {
+                Some((name, _)) if name.trim() == "b" => format!("{}=", name),
+                _ => tag.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(";");
+        data.push_str(&relaxed_header("DKIM-Signature", &unsigned));
+
+        let signature: [u8; 64] = STANDARD
+            .decode(tags["b"].split_whitespace().collect::<String>())
+            .unwrap()
+            .try_into()
+            .unwrap();
+        // RFC 8463: Ed25519 signs the SHA-256 hash of the header data
+        signing_key
+            .verifying_key()
+            .verify(&Sha256::digest(data.as_bytes()), &Signature::from_bytes(&signature))
+            .unwrap();
+    }
+}
@@ -0,0 +1,755 @@
+use std::collections::HashMap;
+
+/// Separator between items of a list-valued argument used with `{#each}`.
+pub const LIST_SEPARATOR: char = ';';
+
+/// Result of rendering a template string for one recipient.
+#[derive(Debug, Clone, Default)]
+pub struct Rendered {
+    pub text: String,
+    /// Keys whose value was needed but missing or empty, with no default.
+    pub missing: Vec<String>,
+}
+
+/// How inserted values are escaped for the output they end up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Escape {
+    /// Values are inserted verbatim (subject, plain-text part).
+    None,
+    /// Values are HTML-escaped unless marked `:raw` (HTML part).
+    Html,
+    /// Like `Html`, and Markdown punctuation is backslash-escaped too so a
+    /// value can't turn into emphasis, links or list markers.
+    Markdown,
+    /// Values are percent-encoded unless marked `:raw` (URLs in headers).
+    Url,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Filter {
+    Upper,
+    Lower,
+    Title,
+    Trim,
+    /// Marks a trusted value that is inserted into HTML without escaping.
+    Raw,
+}
+
+impl Filter {
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "upper" => Some(Filter::Upper),
+            "lower" => Some(Filter::Lower),
+            "title" => Some(Filter::Title),
+            "trim" => Some(Filter::Trim),
+            "raw" => Some(Filter::Raw),
+            _ => None,
+        }
+    }
+
+    fn apply(self, value: &str) -> String {
+        match self {
+            Filter::Upper => value.to_uppercase(),
+            Filter::Lower => value.to_lowercase(),
+            Filter::Trim => value.trim().to_string(),
+            Filter::Raw => value.to_string(),
+            Filter::Title => {
+                let mut out = String::with_capacity(value.len());
+                let mut at_word_start = true;
+                for c in value.chars() {
+                    if at_word_start {
+                        out.extend(c.to_uppercase());
+                    } else {
+                        out.extend(c.to_lowercase());
+                    }
+                    at_word_start = c.is_whitespace() || c == '-';
+                }
+                out
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    /// True when the value is present and non-empty.
+    Truthy(String),
+    Not(String),
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl Condition {
+    fn parse(expr: &str) -> Option<Self> {
+        let expr = expr.trim();
+        let split = |op: &str| {
+            expr.split_once(op)
+                .map(|(k, v)| (k.trim().to_string(), unquote(v.trim()).to_string()))
+        };
+        let cond = if let Some((key, value)) = split("!=") {
+            Condition::NotEquals(key, value)
+        } else if let Some((key, value)) = split("==") {
+            Condition::Equals(key, value)
+        } else if let Some(key) = expr.strip_prefix('!') {
+            Condition::Not(key.trim().to_string())
+        } else {
+            Condition::Truthy(expr.to_string())
+        };
+        let key = match &cond {
+            Condition::Truthy(k) | Condition::Not(k) => k,
+            Condition::Equals(k, _) | Condition::NotEquals(k, _) => k,
+        };
+        is_valid_key(key).then_some(cond)
+    }
+
+    fn key(&self) -> &str {
+        match self {
+            Condition::Truthy(k) | Condition::Not(k) => k,
+            Condition::Equals(k, _) | Condition::NotEquals(k, _) => k,
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value.strip_prefix(quote).and_then(|v| v.strip_suffix(quote)) {
+            return inner;
+        }
+    }
+    value
+}
+
+/// `.` is the current `{#each}` item; anything else is an argument name.
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(['{', '}', '|', ':', '#', '/', '"', '\''])
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text(String),
+    Var {
+        key: String,
+        filters: Vec<Filter>,
+        default: Option<String>,
+    },
+    If {
+        condition: Condition,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        key: String,
+        body: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Tag {
+    Var {
+        key: String,
+        filters: Vec<Filter>,
+        default: Option<String>,
+    },
+    If(Condition),
+    Else,
+    EndIf,
+    Each(String),
+    EndEach,
+}
+
+impl Tag {
+    fn parse(inner: &str) -> Option<Self> {
+        // `{ name }` isn't a placeholder, so CSS like `{ color: red }` stays text
+        if inner != inner.trim() {
+            return None;
+        }
+        if let Some(cond) = inner.strip_prefix("#if ") {
+            return Condition::parse(cond).map(Tag::If);
+        }
+        if let Some(key) = inner.strip_prefix("#each ") {
+            let key = key.trim();
+            return is_valid_key(key).then(|| Tag::Each(key.to_string()));
+        }
+        match inner {
+            "#else" => return Some(Tag::Else),
+            "/if" => return Some(Tag::EndIf),
+            "/each" => return Some(Tag::EndEach),
+            _ => {}
+        }
+
+        let (expr, default) = match inner.split_once('|') {
+            Some((expr, default)) => (expr, Some(default.to_string())),
+            None => (inner, None),
+        };
+        let mut parts = expr.split(':');
+        let key = parts.next().unwrap_or_default().trim();
+        if !is_valid_key(key) {
+            return None;
+        }
+        let filters = parts.map(Filter::parse).collect::<Option<Vec<_>>>()?;
+        Some(Tag::Var {
+            key: key.to_string(),
+            filters,
+            default,
+        })
+    }
+
+    fn is_block(&self) -> bool {
+        !matches!(self, Tag::Var { .. })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    Text(String),
+    /// A parsed tag and its original source, used if it turns out misplaced.
+    Tag(Tag, String),
+}
+
+/// Splits `text` into literal text and `{...}` tags. Braces that don't form a
+/// valid tag (CSS rules, stray braces) stay literal text. A placeholder in
+/// doubled braces, as other template languages write it, also stays literal
+/// and is reported in `errors`.
+fn tokenize(text: &str, errors: &mut Vec<String>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut rest = text;
+
+    while let Some(open) = rest.find('{') {
+        literal.push_str(&rest[..open]);
+        if let Some(inner) = rest[open..].strip_prefix("{{")
+            && let Some(close) = inner.find("}}")
+            && !inner[..close].contains(['{', '}'])
+            && matches!(Tag::parse(&inner[..close]), Some(Tag::Var { .. }))
+        {
+            let source = &rest[open..open + close + 4];
+            errors.push(format!("{} has doubled braces; write {{{}}}", source, &inner[..close]));
+            literal.push_str(source);
+            rest = &rest[open + close + 4..];
+            continue;
+        }
+        let after = &rest[open + 1..];
+        let close = after.find('}');
+        let next_open = after.find('{');
+        match close {
+            Some(close) if next_open.is_none_or(|o| o > close) => {
+                let source = &rest[open..open + close + 2];
+                match Tag::parse(&after[..close]) {
+                    Some(tag) => {
+                        if !literal.is_empty() {
+                            tokens.push(Token::Text(std::mem::take(&mut literal)));
+                        }
+                        tokens.push(Token::Tag(tag, source.to_string()));
+                    }
+                    None => literal.push_str(source),
+                }
+                rest = &after[close + 1..];
+            }
+            _ => {
+                literal.push('{');
+                rest = after;
+            }
+        }
+    }
+    literal.push_str(rest);
+    if !literal.is_empty() {
+        tokens.push(Token::Text(literal));
+    }
+
+    strip_standalone_block_lines(&mut tokens);
+    tokens
+}
+
+/// A block tag alone on its line shouldn't leave an empty line behind, so the
+/// surrounding indentation and the line break after it are removed.
+fn strip_standalone_block_lines(tokens: &mut [Token]) {
+    let standalone: Vec<bool> = (0..tokens.len())
+        .map(|i| {
+            if !matches!(&tokens[i], Token::Tag(tag, _) if tag.is_block()) {
+                return false;
+            }
+            let before_ok = match i.checked_sub(1).map(|j| &tokens[j]) {
+                None => true,
+                Some(Token::Text(t)) => {
+                    let line = &t[t.rfind('\n').map_or(0, |p| p + 1)..];
+                    line.trim().is_empty() && (t.contains('\n') || i == 1)
+                }
+                Some(Token::Tag(..)) => false,
+            };
+            let after_ok = match tokens.get(i + 1) {
+                None => true,
+                Some(Token::Text(t)) => t.split('\n').next().unwrap_or_default().trim().is_empty(),
+                Some(Token::Tag(..)) => false,
+            };
+            before_ok && after_ok
+        })
+        .collect();
+
+    for (i, _) in standalone.iter().enumerate().filter(|(_, s)| **s) {
+        if let Some(Token::Text(t)) = i.checked_sub(1).map(|j| &mut tokens[j]) {
+            let keep = t.rfind('\n').map_or(0, |p| p + 1);
+            t.truncate(keep);
+        }
+        if let Some(Token::Text(t)) = tokens.get_mut(i + 1) {
+            match t.find('\n') {
+                Some(p) => {
+                    t.drain(..=p);
+                }
+                None => t.clear(),
+            }
+        }
+    }
+}
+
+/// Builds the node tree. Misplaced `{#else}`/`{/if}`/`{/each}` tags are kept
+/// as literal text and unclosed blocks end at the end of the input; both are
+/// reported in `errors`.
+fn parse(tokens: Vec<Token>, errors: &mut Vec<String>) -> Vec<Node> {
+    enum Frame {
+        If {
+            condition: Condition,
+            source: String,
+            then: Option<Vec<Node>>,
+        },
+        Each {
+            key: String,
+            source: String,
+        },
+    }
+
+    let mut stack: Vec<(Frame, Vec<Node>)> = Vec::new();
+    let mut current: Vec<Node> = Vec::new();
+
+    for token in tokens {
+        match token {
+            Token::Text(text) => current.push(Node::Text(text)),
+            Token::Tag(Tag::Var { key, filters, default }, _) => {
+                current.push(Node::Var { key, filters, default })
+            }
+            Token::Tag(Tag::If(condition), source) => {
+                stack.push((
+                    Frame::If {
+                        condition,
+                        source,
+                        then: None,
+                    },
+                    std::mem::take(&mut current),
+                ));
+            }
+            Token::Tag(Tag::Each(key), source) => {
+                stack.push((Frame::Each { key, source }, std::mem::take(&mut current)));
+            }
+            Token::Tag(Tag::Else, source) => match stack.last_mut() {
+                Some((Frame::If { then: then @ None, .. }, _)) => {
+                    *then = Some(std::mem::take(&mut current));
+                }
+                _ => {
+                    errors.push(format!("{} without a matching {{#if}}", source));
+                    current.push(Node::Text(source));
+                }
+            },
+            Token::Tag(Tag::EndIf, source) => match stack.pop() {
+                Some((Frame::If { condition, then, .. }, parent)) => {
+                    let branch = std::mem::replace(&mut current, parent);
+                    let (then, otherwise) = match then {
+                        Some(then) => (then, branch),
+                        None => (branch, Vec::new()),
+                    };
+                    current.push(Node::If {
+                        condition,
+                        then,
+                        otherwise,
+                    });
+                }
+                other => {
+                    stack.extend(other);
+                    errors.push(format!("{} without a matching {{#if}}", source));
+                    current.push(Node::Text(source));
+                }
+            },
+            Token::Tag(Tag::EndEach, source) => match stack.pop() {
+                Some((Frame::Each { key, .. }, parent)) => {
+                    let body = std::mem::replace(&mut current, parent);
+                    current.push(Node::Each { key, body });
+                }
+                other => {
+                    stack.extend(other);
+                    errors.push(format!("{} without a matching {{#each}}", source));
+                    current.push(Node::Text(source));
+                }
+            },
+        }
+    }
+
+    while let Some((frame, parent)) = stack.pop() {
+        let inner = std::mem::replace(&mut current, parent);
+        match frame {
+            Frame::If {
+                condition,
+                source,
+                then,
+            } => {
+                errors.push(format!("{} is never closed with {{/if}}", source));
+                let (then, otherwise) = match then {
+                    Some(then) => (then, inner),
+                    None => (inner, Vec::new()),
+                };
+                current.push(Node::If {
+                    condition,
+                    then,
+                    otherwise,
+                });
+            }
+            Frame::Each { key, source } => {
+                errors.push(format!("{} is never closed with {{/each}}", source));
+                current.push(Node::Each { key, body: inner });
+            }
+        }
+    }
+
+    current
+}
+
+struct Context<'a> {
+    args: &'a HashMap<String, String>,
+    escape: Escape,
+    items: Vec<&'a str>,
+    out: String,
+    missing: Vec<String>,
+}
+
+impl<'a> Context<'a> {
+    fn lookup(&self, key: &str) -> Option<&'a str> {
+        if key == "." {
+            self.items.last().copied()
+        } else {
+            self.args.get(key).map(String::as_str)
+        }
+    }
+
+    fn test(&self, condition: &Condition) -> bool {
+        let value = self.lookup(condition.key()).unwrap_or_default();
+        match condition {
+            Condition::Truthy(_) => !value.trim().is_empty(),
+            Condition::Not(_) => value.trim().is_empty(),
+            Condition::Equals(_, expected) => value.trim() == expected,
+            Condition::NotEquals(_, expected) => value.trim() != expected,
+        }
+    }
+
+    fn render(&mut self, nodes: &'a [Node]) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.out.push_str(text),
+                Node::Var {
+                    key,
+                    filters,
+                    default,
+                } => match self.lookup(key) {
+                    Some(value) if !value.is_empty() => {
+                        let value = filters
+                            .iter()
+                            .fold(value.to_string(), |v, f| f.apply(&v));
+                        match self.escape {
+                            _ if filters.contains(&Filter::Raw) => self.out.push_str(&value),
+                            Escape::None => self.out.push_str(&value),
+                            Escape::Html => self.out.push_str(&escape_html(&value)),
+                            Escape::Markdown => self.out.push_str(&escape_markdown(&value)),
+                            Escape::Url => self.out.push_str(&escape_url(&value)),
+                        }
+                    }
+                    value => {
+                        if let Some(default) = default {
+                            self.out.push_str(default);
+                        } else {
+                            if !self.missing.contains(key) {
+                                self.missing.push(key.clone());
+                            }
+                            // Unknown keys stay visible so mistakes are easy to spot.
+                            if value.is_none() {
+                                self.out.push_str(&format!("{{{}}}", key));
+                            }
+                        }
+                    }
+                },
+                Node::If {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    if self.test(condition) {
+                        self.render(then);
+                    } else {
+                        self.render(otherwise);
+                    }
+                }
+                Node::Each { key, body } => {
+                    let list = self.lookup(key).unwrap_or_default();
+                    for item in list.split(LIST_SEPARATOR).map(str::trim).filter(|i| !i.is_empty()) {
+                        self.items.push(item);
+                        self.render(body);
+                        self.items.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renders `text` with the recipient's `args`, escaping inserted values as
+/// `escape` says. Text written in the template itself, including `|` default
+/// values, is never escaped.
+///
+/// Supported syntax:
+/// - `{name}` inserts a value; `{name|there}` falls back to `there` when the
+///   value is missing or empty.
+/// - `{name:upper}` applies filters (`upper`, `lower`, `title`, `trim`),
+///   which can be chained: `{name:trim:title|there}`. `{bio:raw}` inserts a
+///   trusted value into HTML without escaping.
+/// - `{#if key}…{#else}…{/if}` tests for a non-empty value; conditions can
+///   also be `!key`, `key == value` and `key != value`.
+/// - `{#each items}…{.}…{/each}` repeats for each `;`-separated item.
+///
+/// Tags have no spaces just inside their braces: `{ name }` is literal text,
+/// as is `{{name}}`, which [`syntax_errors`] also reports.
+pub fn render(text: &str, args: &HashMap<String, String>, escape: Escape) -> Rendered {
+    let nodes = parse(tokenize(text, &mut Vec::new()), &mut Vec::new());
+    let mut ctx = Context {
+        args,
+        escape,
+        items: Vec::new(),
+        out: String::with_capacity(text.len()),
+        missing: Vec::new(),
+    };
+    ctx.render(&nodes);
+    Rendered {
+        text: ctx.out,
+        missing: ctx.missing,
+    }
+}
+
+pub fn escape_html(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn escape_markdown(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' | '<' | '>' | '"' | '\'' => out.push_str(&escape_html(c.encode_utf8(&mut [0; 4]))),
+            '\\' | '`' | '*' | '_' | '{' | '}' | '[' | ']' | '(' | ')' | '#' | '+' | '-' | '.'
+            | '!' | '|' | '~' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters, so a value
+/// stays a single path segment or query value.
+pub fn escape_url(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+/// Every argument key referenced by `text`, in order of first appearance.
+pub fn referenced_keys(text: &str) -> Vec<String> {
+    fn add(keys: &mut Vec<String>, key: &str) {
+        if key != "." && !keys.iter().any(|k| k == key) {
+            keys.push(key.to_string());
+        }
+    }
+
+    fn walk(nodes: &[Node], keys: &mut Vec<String>) {
+        for node in nodes {
+            match node {
+                Node::Text(_) => {}
+                Node::Var { key, .. } => add(keys, key),
+                Node::If {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    add(keys, condition.key());
+                    walk(then, keys);
+                    walk(otherwise, keys);
+                }
+                Node::Each { key, body } => {
+                    add(keys, key);
+                    walk(body, keys);
+                }
+            }
+        }
+    }
+
+    let mut keys = Vec::new();
+    walk(&parse(tokenize(text, &mut Vec::new()), &mut Vec::new()), &mut keys);
+    keys
+}
+
+/// Structural problems in `text`, such as an `{#if}` that is never closed or
+/// a placeholder in doubled braces.
+pub fn syntax_errors(text: &str) -> Vec<String> {
+    let mut errors = Vec::new();
+    let tokens = tokenize(text, &mut errors);
+    parse(tokens, &mut errors);
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn render_with(text: &str, pairs: &[(&str, &str)]) -> String {
+        render(text, &args(pairs), Escape::None).text
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let text = "{#if vip}VIP{#if tier == gold} gold{#else} {tier}{/if}{#else}regular{/if}";
+        assert_eq!(render_with(text, &[("vip", "yes"), ("tier", "gold")]), "VIP gold");
+        assert_eq!(render_with(text, &[("vip", "yes"), ("tier", "silver")]), "VIP silver");
+        assert_eq!(render_with(text, &[("vip", " ")]), "regular");
+
+        let text = "{#each teams}{#if !lead}{.}{/if}{#each members}[{.}]{/each};{/each}";
+        assert_eq!(render_with(text, &[("teams", "a;b"), ("members", "x;y")]), "a[x][y];b[x][y];");
+        assert!(syntax_errors(text).is_empty());
+    }
+
+    #[test]
+    fn block_lines_leave_no_blank_lines() {
+        let text = "Hi\n{#if vip}\nWelcome back\n{/if}\nBye";
+        assert_eq!(render_with(text, &[("vip", "1")]), "Hi\nWelcome back\nBye");
+        assert_eq!(render_with(text, &[]), "Hi\nBye");
+    }
+
+    #[test]
+    fn unclosed_and_mismatched_blocks_are_reported() {
+        assert_eq!(syntax_errors("{#if a}x"), vec!["{#if a} is never closed with {/if}"]);
+        assert_eq!(syntax_errors("{#each a}x"), vec!["{#each a} is never closed with {/each}"]);
+        assert_eq!(syntax_errors("x{/if}"), vec!["{/if} without a matching {#if}"]);
+        assert_eq!(syntax_errors("x{#else}"), vec!["{#else} without a matching {#if}"]);
+        assert_eq!(syntax_errors("{#each a}x{/if}{/each}"), vec!["{/if} without a matching {#if}"]);
+        assert_eq!(syntax_errors("{#if a}x{/each}{/if}"), vec!["{/each} without a matching {#each}"]);
+        // Misplaced tags stay visible in the output
+        assert_eq!(render_with("x{/if}", &[]), "x{/if}");
+        // An unclosed block still renders up to the end
+        assert_eq!(render_with("{#if a}yes", &[("a", "1")]), "yes");
+    }
+
+    #[test]
+    fn unknown_filters_leave_the_braces_as_text() {
+        assert_eq!(render_with("{name:shout}", &[("name", "jane")]), "{name:shout}");
+        assert_eq!(render_with("{name:trim:title}", &[("name", " jane doe-smith ")]), "Jane Doe-Smith");
+        assert_eq!(render_with("{name:upper}", &[("name", "jane")]), "JANE");
+        assert!(referenced_keys("{name:shout}").is_empty());
+    }
+
+    #[test]
+    fn each_splits_on_semicolons() {
+        let text = "{#each items}<{.}>{/each}";
+        assert_eq!(render_with(text, &[("items", "a; b ;;c")]), "<a><b><c>");
+        assert_eq!(render_with(text, &[("items", "")]), "");
+        assert_eq!(render_with(text, &[("items", " ; ")]), "");
+        assert_eq!(render_with(text, &[]), "");
+    }
+
+    #[test]
+    fn fallbacks_cover_missing_and_empty_values() {
+        assert_eq!(render_with("Hi {name|there}", &[("name", "")]), "Hi there");
+        assert_eq!(render_with("Hi {name|there}", &[]), "Hi there");
+        assert_eq!(render_with("Hi {name|there}", &[("name", "Jane")]), "Hi Jane");
+        assert_eq!(render_with("Hi {name|}", &[]), "Hi ");
+
+        // Without a fallback, empty values render as nothing and unknown keys stay visible
+        let rendered = render("{a}/{b}", &args(&[("a", "")]), Escape::None);
+        assert_eq!(rendered.text, "/{b}");
+        assert_eq!(rendered.missing, vec!["a", "b"]);
+        assert!(render("{a|x}", &args(&[]), Escape::None).missing.is_empty());
+    }
+
+    #[test]
+    fn referenced_keys_in_order() {
+        let text = "{greeting|Hi} {name}{#if vip}{perk}{/if}{#each items}{.}{name}{/each}{#if tier == gold}{/if}";
+        assert_eq!(referenced_keys(text), vec!["greeting", "name", "vip", "perk", "items", "tier"]);
+    }
+
+    #[test]
+    fn padded_and_doubled_braces_are_not_placeholders() {
+        assert_eq!(render_with("{ name }", &[("name", "Jane")]), "{ name }");
+        assert_eq!(render_with("p { color: red }", &[]), "p { color: red }");
+        assert!(syntax_errors("{ name }").is_empty());
+
+        assert_eq!(render_with("{{name}}", &[("name", "Jane")]), "{{name}}");
+        assert_eq!(syntax_errors("Hi {{name}}"), vec!["{{name}} has doubled braces; write {name}"]);
+        assert!(referenced_keys("{{name}}").is_empty());
+    }
+
+    #[test]
+    fn html_escaping() {
+        assert_eq!(
+            escape_html(r#"<script>alert("x" + 'y' & 1)</script>"#),
+            "&lt;script&gt;alert(&quot;x&quot; + &#39;y&#39; &amp; 1)&lt;/script&gt;"
+        );
+        assert_eq!(escape_html("Zoë – naïve"), "Zoë – naïve");
+    }
+
+    #[test]
+    fn markdown_escaping_neutralizes_line_starts() {
+        assert_eq!(escape_markdown("1. first"), "1\\. first");
+        assert_eq!(escape_markdown("# Heading"), "\\# Heading");
+        assert_eq!(escape_markdown("* item\n- item\n+ item"), "\\* item\n\\- item\n\\+ item");
+        assert_eq!(escape_markdown("[link](http://x)"), "\\[link\\]\\(http://x\\)");
+        assert_eq!(escape_markdown("<b>\"Tom's\"</b>"), "&lt;b&gt;&quot;Tom&#39;s&quot;&lt;/b&gt;");
+    }
+
+    #[test]
+    fn url_escaping_encodes_everything_but_unreserved() {
+        assert_eq!(escape_url("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(escape_url("a b&c=d/e?f#g"), "a%20b%26c%3Dd%2Fe%3Ff%23g");
+        // UTF-8 bytes are encoded one by one
+        assert_eq!(escape_url("Zoë"), "Zo%C3%AB");
+        assert_eq!(escape_url("日"), "%E6%97%A5");
+    }
+
+    #[test]
+    fn raw_values_bypass_escaping() {
+        let bio = args(&[("bio", "<b>1. *bold*</b> & more")]);
+        for escape in [Escape::Html, Escape::Markdown, Escape::Url] {
+            let rendered = render("{bio:raw}|{bio}", &bio, escape).text;
+            let (raw, escaped) = rendered.split_once('|').unwrap();
+            assert_eq!(raw, "<b>1. *bold*</b> & more", "{:?}", escape);
+            assert_ne!(escaped, raw, "{:?}", escape);
+        }
+        assert_eq!(
+            render("{#each tags}{.}{/each}", &args(&[("tags", "<i>")]), Escape::Html).text,
+            "&lt;i&gt;"
+        );
+    }
+}
@@ -1,22 +1,56 @@
 mod app;
 mod config;
+mod contacts;
 mod email;
+mod engine;
+mod markdown;
+mod oauth;
+mod paths;
+mod plaintext;
+mod richtext;
+mod secret;
 mod template;
+mod validate;
 
 use app::EmailApp;
-use config::SmtpConfig;
+use config::Settings;
+use paths::{ArgsError, Paths};
 use template::load_templates;
 
 fn main() {
-    let smtp_config = match SmtpConfig::load() {
-        Ok(cfg) => cfg,
+    let paths = match Paths::resolve(std::env::args().skip(1)) {
+        Ok(paths) => paths,
+        Err(ArgsError::Help) => {
+            println!("{}", paths::USAGE);
+            return;
+        }
+        Err(ArgsError::Invalid(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let migrated = match paths.init() {
+        Ok(migrated) => migrated,
         Err(e) => {
-            eprintln!("Failed to load Settings.toml: {}", e);
-            eprintln!("Please ensure src/Settings.toml exists with host, port, username, password, from_name fields.");
+            eprintln!("Could not create the data directory {}: {}", paths.data_dir.display(), e);
             std::process::exit(1);
         }
     };
 
+    // Without a settings file the app starts with the setup wizard
+    let settings = if paths.config_file.exists() {
+        match Settings::load(&paths.config_file) {
+            Ok(cfg) => Some(cfg),
+            Err(e) => {
+                eprintln!("Failed to load {}: {}", paths.config_file.display(), e);
+                eprintln!("Please ensure it has host, username, from_name and a password source, or a [[profiles]] list of them.");
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
     let templates = load_templates();
 
     let options = eframe::NativeOptions {
@@ -29,8 +63,9 @@ fn main() {
     let _ = eframe::run_native(
         "Bulk Email Sender",
         options,
-        Box::new(move |_cc| {
-            Ok(Box::new(EmailApp::new(smtp_config, templates)))
+        Box::new(move |cc| {
+            egui_extras::install_image_loaders(&cc.egui_ctx);
+            Ok(Box::new(EmailApp::new(settings, templates, paths, migrated)))
         }),
     );
 }
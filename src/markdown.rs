@@ -0,0 +1,48 @@
+use pulldown_cmark::{html, Event, Options, Parser};
+
+/// Converts a Markdown body to HTML for the email. Tables and strikethrough
+/// are supported, inline HTML passes through, and single line breaks are
+/// kept as `<br>` like they are in HTML bodies.
+pub fn to_html(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    let parser = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::SoftBreak => Event::HardBreak,
+        other => other,
+    });
+    let mut out = String::with_capacity(markdown.len() * 3 / 2);
+    html::push_html(&mut out, parser);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::{EmailTemplate, InlineImage};
+
+    #[test]
+    fn single_line_breaks_are_kept() {
+        assert_eq!(to_html("Hi Jane,\nthanks!\n\nBye"), "<p>Hi Jane,<br />\nthanks!</p>\n<p>Bye</p>\n");
+    }
+
+    #[test]
+    fn tables_and_strikethrough() {
+        let html = to_html("| Name | Plan |\n|------|------|\n| Jane | ~~Free~~ Pro |\n");
+        assert!(html.contains("<th>Name</th>"), "{}", html);
+        assert!(html.contains("<td>Jane</td>"), "{}", html);
+        assert!(html.contains("<td><del>Free</del> Pro</td>"), "{}", html);
+    }
+
+    #[test]
+    fn cid_images_survive_for_inline_attachments() {
+        let html = to_html("![Our logo](cid:logo.png) and ![chart](cid:chart)");
+        assert!(html.contains("<img src=\"cid:logo.png\" alt=\"Our logo\" />"), "{}", html);
+
+        let mut template = EmailTemplate::new("Test".to_string());
+        template.inline_images = ["logo", "logo.png", "chart", "unused"]
+            .iter()
+            .map(|cid| InlineImage { cid: cid.to_string(), path: format!("{}.png", cid).into() })
+            .collect();
+        let found: Vec<&str> = template.referenced_images(&html).iter().map(|img| img.cid.as_str()).collect();
+        assert_eq!(found, vec!["logo.png", "chart"]);
+    }
+}
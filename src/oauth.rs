@@ -0,0 +1,526 @@
+use crate::config::{OAuth2Settings, SmtpConfig};
+use crate::paths;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+use ureq::tls::{RootCerts, TlsConfig, TlsProvider};
+use url::{Host, Url};
+
+const TOKENS_FILE: &str = "oauth_tokens.json";
+
+/// How long the browser sign-in may take before giving up.
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Access tokens this close to expiry are refreshed before use.
+const EXPIRY_MARGIN_SECS: u64 = 300;
+
+/// Tokens from a completed sign-in. Not `Debug`, so they can't end up in logs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OAuthToken {
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Unix time at which `access_token` stops working.
+    pub expires_at: u64,
+}
+
+impl OAuthToken {
+    /// Whether the access token is expired or about to be.
+    pub fn is_expiring(&self) -> bool {
+        self.expires_at <= now_secs() + EXPIRY_MARGIN_SECS
+    }
+}
+
+/// Why no usable token could be had.
+#[derive(Debug)]
+pub enum TokenError {
+    /// The token endpoint couldn't be reached, timed out, or answered with a
+    /// server-side error (5xx, 429); trying again later may work.
+    Unavailable(String),
+    /// Anything else, e.g. not signed in or a revoked refresh token.
+    Failed(String),
+}
+
+impl TokenError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, TokenError::Unavailable(_))
+    }
+
+    /// Rewords the message, keeping the kind.
+    fn map(self, f: impl FnOnce(String) -> String) -> Self {
+        match self {
+            TokenError::Unavailable(message) => TokenError::Unavailable(f(message)),
+            TokenError::Failed(message) => TokenError::Failed(f(message)),
+        }
+    }
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Unavailable(message) | TokenError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+impl From<String> for TokenError {
+    fn from(message: String) -> Self {
+        TokenError::Failed(message)
+    }
+}
+
+impl From<&str> for TokenError {
+    fn from(message: &str) -> Self {
+        TokenError::Failed(message.to_string())
+    }
+}
+
+impl From<TokenError> for String {
+    fn from(error: TokenError) -> Self {
+        error.to_string()
+    }
+}
+
+/// Tokens are stored per account and token endpoint, so renaming a profile
+/// doesn't sign it out.
+fn token_key(config: &SmtpConfig, oauth: &OAuth2Settings) -> String {
+    format!("{} {}", config.username, oauth.token_url())
+}
+
+fn load_tokens() -> HashMap<String, OAuthToken> {
+    std::fs::read_to_string(paths::data_file(TOKENS_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_token(key: String, token: &OAuthToken) -> Result<(), String> {
+    let mut tokens = load_tokens();
+    tokens.insert(key, token.clone());
+    let data = serde_json::to_string_pretty(&tokens).map_err(|e| e.to_string())?;
+
+    paths::write_private(&paths::data_file(TOKENS_FILE), data.as_bytes())
+        .map_err(|e| format!("could not save {}: {}", TOKENS_FILE, e))
+}
+
+/// Whether the profile has a stored sign-in (possibly needing a refresh).
+pub fn is_signed_in(config: &SmtpConfig) -> bool {
+    config
+        .oauth2
+        .as_ref()
+        .is_some_and(|oauth| load_tokens().contains_key(&token_key(config, oauth)))
+}
+
+/// A usable token for the profile: the stored one, or a refreshed one when it
+/// is about to expire.
+pub fn current_token(config: &SmtpConfig) -> Result<OAuthToken, TokenError> {
+    let oauth = config
+        .oauth2
+        .as_ref()
+        .ok_or_else(|| format!("profile '{}' does not use OAuth2", config.name))?;
+    let key = token_key(config, oauth);
+    let not_signed_in = || format!("profile '{}' is not signed in; use Sign in next to Send from", config.name);
+
+    let token = load_tokens().remove(&key).ok_or_else(not_signed_in)?;
+    if !token.is_expiring() {
+        return Ok(token);
+    }
+    let refresh_token = token.refresh_token.ok_or_else(not_signed_in)?;
+    let refreshed = refresh(oauth, refresh_token)
+        .map_err(|e| e.map(|e| format!("refreshing the OAuth2 token for '{}' failed: {}", config.name, e)))?;
+    save_token(key, &refreshed)?;
+    Ok(refreshed)
+}
+
+/// Trades a refresh token for a new access token.
+fn refresh(oauth: &OAuth2Settings, refresh_token: String) -> Result<OAuthToken, TokenError> {
+    let mut refreshed = request_token(
+        oauth,
+        &[("grant_type", "refresh_token"), ("refresh_token", &refresh_token)],
+    )?;
+    // Providers only sometimes rotate the refresh token
+    refreshed.refresh_token.get_or_insert(refresh_token);
+    Ok(refreshed)
+}
+
+/// Runs the authorization-code flow with PKCE: opens the provider's consent
+/// page in the browser, waits for the redirect to a loopback listener, then
+/// exchanges the code for tokens and stores them. Blocks until done, so call
+/// it off the UI thread.
+pub fn sign_in(config: &SmtpConfig) -> Result<(), String> {
+    let oauth = config
+        .oauth2
+        .as_ref()
+        .ok_or_else(|| format!("profile '{}' does not use OAuth2", config.name))?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").map_err(|e| e.to_string())?;
+    let port = listener.local_addr().map_err(|e| e.to_string())?.port();
+    let redirect_uri = format!("http://127.0.0.1:{}/", port);
+
+    let verifier = random_token();
+    let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .encode(Sha256::digest(verifier.as_bytes()));
+    let state = random_token();
+
+    let mut auth_url = Url::parse(&oauth.auth_url()).map_err(|e| format!("auth_url: {}", e))?;
+    auth_url
+        .query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &oauth.client_id)
+        .append_pair("redirect_uri", &redirect_uri)
+        .append_pair("scope", &oauth.scope())
+        .append_pair("state", &state)
+        .append_pair("code_challenge", &challenge)
+        .append_pair("code_challenge_method", "S256")
+        .append_pair("login_hint", &config.username)
+        // Google only returns a refresh token for offline access
+        .append_pair("access_type", "offline")
+        .append_pair("prompt", "consent");
+    webbrowser::open(auth_url.as_str())
+        .map_err(|e| format!("could not open the browser ({}); visit {}", e, auth_url))?;
+
+    let code = wait_for_redirect(&listener, &state)?;
+    let token = exchange_code(oauth, &code, &redirect_uri, &verifier)?;
+    save_token(token_key(config, oauth), &token)
+}
+
+/// Trades the authorization code from the redirect, with the PKCE verifier
+/// its challenge was made from, for tokens.
+fn exchange_code(
+    oauth: &OAuth2Settings,
+    code: &str,
+    redirect_uri: &str,
+    verifier: &str,
+) -> Result<OAuthToken, TokenError> {
+    request_token(
+        oauth,
+        &[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("code_verifier", verifier),
+        ],
+    )
+}
+
+/// Accepts loopback connections until the provider redirects back with a
+/// code (or an error) for our `state`.
+fn wait_for_redirect(listener: &TcpListener, state: &str) -> Result<String, String> {
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+    let deadline = Instant::now() + SIGN_IN_TIMEOUT;
+    loop {
+        let mut stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err("timed out waiting for the browser sign-in".to_string());
+                }
+                std::thread::sleep(Duration::from_millis(100));
+                continue;
+            }
+            Err(e) => return Err(e.to_string()),
+        };
+        let _ = stream.set_nonblocking(false);
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+
+        let Some(target) = read_request_target(&mut stream) else {
+            continue;
+        };
+        let params: HashMap<String, String> = Url::parse(&format!("http://127.0.0.1{}", target))
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+        // Browsers also ask for /favicon.ico and the like
+        if !params.contains_key("code") && !params.contains_key("error") {
+            let _ = stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+            continue;
+        }
+
+        let result = if params.get("state").map(String::as_str) != Some(state) {
+            Err("sign-in response did not match the request (state mismatch)".to_string())
+        } else if let Some(error) = params.get("error") {
+            Err(match params.get("error_description") {
+                Some(description) => format!("{}: {}", error, description),
+                None => error.clone(),
+            })
+        } else {
+            Ok(params["code"].clone())
+        };
+        let page = match &result {
+            Ok(_) => "Signed in. You can close this tab and return to Email Sender.",
+            Err(_) => "Sign-in failed. See the status log in Email Sender.",
+        };
+        let _ = stream.write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                page.len(),
+                page
+            )
+            .as_bytes(),
+        );
+        return result;
+    }
+}
+
+/// Reads an HTTP request head and returns its target, e.g. `/?code=..`.
+fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 16 * 1024 {
+        let n = stream.read(&mut buf).ok().filter(|&n| n > 0)?;
+        head.extend_from_slice(&buf[..n]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let mut parts = head.lines().next()?.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) => Some(target.to_string()),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+/// Calls the token endpoint with `grant` plus the client credentials.
+fn request_token(oauth: &OAuth2Settings, grant: &[(&str, &str)]) -> Result<OAuthToken, TokenError> {
+    let mut params = grant.to_vec();
+    params.push(("client_id", &oauth.client_id));
+    if let Some(secret) = &oauth.client_secret {
+        params.push(("client_secret", secret.expose()));
+    }
+    let body = post_form(&oauth.token_url(), &params)?;
+    let response: TokenResponse =
+        serde_json::from_str(&body).map_err(|e| format!("unexpected token response: {}", e))?;
+    Ok(OAuthToken {
+        access_token: response.access_token,
+        refresh_token: response.refresh_token,
+        // Tokens without a lifetime are treated as good for an hour
+        expires_at: now_secs() + response.expires_in.unwrap_or(3600),
+    })
+}
+
+/// POSTs a form to `endpoint` and returns the response body. Plain `http` is
+/// only allowed for loopback addresses, e.g. a local stand-in token endpoint.
+fn post_form(endpoint: &str, params: &[(&str, &str)]) -> Result<String, TokenError> {
+    let url = Url::parse(endpoint).map_err(|e| format!("token_url: {}", e))?;
+    let loopback = match url.host().ok_or("token_url has no host")? {
+        Host::Domain(domain) => domain == "localhost",
+        Host::Ipv4(ip) => ip.is_loopback(),
+        Host::Ipv6(ip) => ip.is_loopback(),
+    };
+    if url.scheme() != "https" && !(url.scheme() == "http" && loopback) {
+        return Err("token_url must use https".into());
+    }
+
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .timeout_global(Some(Duration::from_secs(30)))
+        // Error statuses carry the OAuth2 error in the body
+        .http_status_as_error(false)
+        .tls_config(
+            TlsConfig::builder()
+                .provider(TlsProvider::NativeTls)
+                .root_certs(RootCerts::PlatformVerifier)
+                .build(),
+        )
+        .build()
+        .into();
+    let host = url.host_str().unwrap_or_default();
+    let transport_error = |e: ureq::Error| match e {
+        ureq::Error::Io(_)
+        | ureq::Error::Timeout(_)
+        | ureq::Error::HostNotFound
+        | ureq::Error::ConnectionFailed
+        | ureq::Error::Protocol(_)
+        | ureq::Error::Tls(_) => TokenError::Unavailable(format!("{}: {}", host, e)),
+        e => TokenError::Failed(format!("{}: {}", host, e)),
+    };
+    let mut response = agent
+        .post(url.as_str())
+        .header("Accept", "application/json")
+        .send_form(params.iter().copied())
+        .map_err(transport_error)?;
+    let status = response.status().as_u16();
+    let body = response.body_mut().read_to_string().map_err(transport_error)?;
+
+    if !(200..300).contains(&status) {
+        // OAuth2 errors come as {"error": .., "error_description": ..}
+        let detail = serde_json::from_str::<serde_json::Value>(&body)
+            .ok()
+            .and_then(|json| {
+                let error = json.get("error")?.as_str()?.to_string();
+                Some(match json.get("error_description").and_then(|d| d.as_str()) {
+                    Some(description) => format!("{}: {}", error, description),
+                    None => error,
+                })
+            })
+            .unwrap_or_else(|| body.trim().chars().take(200).collect());
+        let message = format!("token endpoint returned {} ({})", status, detail);
+        return Err(if status >= 500 || status == 429 {
+            TokenError::Unavailable(message)
+        } else {
+            TokenError::Failed(message)
+        });
+    }
+    Ok(body)
+}
+
+/// 96 random hex characters, within the 43–128 allowed for a PKCE verifier.
+fn random_token() -> String {
+    (0..3).map(|_| uuid::Uuid::new_v4().simple().to_string()).collect()
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::JoinHandle;
+
+    /// A stand-in token endpoint on loopback that answers one request with
+    /// `status` and `body`, and hands back the form it was sent.
+    fn token_endpoint(addr: &str, status: &str, body: &'static str) -> (String, JoinHandle<HashMap<String, String>>) {
+        let listener = TcpListener::bind(addr).unwrap();
+        let url = format!("http://{}/token", listener.local_addr().unwrap());
+        let status = status.to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            let body_start = loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                if let Some(i) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break i + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&request[..body_start]).to_ascii_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |value| value.trim().parse().unwrap());
+            while request.len() < body_start + length {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            url::form_urlencoded::parse(&request[body_start..]).into_owned().collect()
+        });
+        (url, server)
+    }
+
+    fn settings(token_url: String) -> OAuth2Settings {
+        OAuth2Settings {
+            provider: crate::config::OAuthProvider::Custom,
+            client_id: "client".to_string(),
+            client_secret: Some(crate::secret::Secret::new("shh")),
+            tenant: None,
+            auth_url: None,
+            token_url: Some(token_url),
+            scope: None,
+        }
+    }
+
+    #[test]
+    fn exchanges_the_code_with_the_pkce_verifier() {
+        let (url, server) = token_endpoint(
+            "127.0.0.1:0",
+            "200 OK",
+            r#"{"access_token":"access","refresh_token":"refresh","expires_in":600,"token_type":"Bearer"}"#,
+        );
+        let token = exchange_code(&settings(url), "the-code", "http://127.0.0.1:1234/", "the-verifier").unwrap();
+        let form = server.join().unwrap();
+
+        assert_eq!(form["grant_type"], "authorization_code");
+        assert_eq!(form["code"], "the-code");
+        assert_eq!(form["redirect_uri"], "http://127.0.0.1:1234/");
+        assert_eq!(form["code_verifier"], "the-verifier");
+        assert_eq!(form["client_id"], "client");
+        assert_eq!(form["client_secret"], "shh");
+        assert_eq!(token.access_token, "access");
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+        assert!(!token.is_expiring());
+    }
+
+    #[test]
+    fn refresh_keeps_the_refresh_token_unless_rotated() {
+        let (url, server) = token_endpoint("127.0.0.1:0", "200 OK", r#"{"access_token":"new","expires_in":3600}"#);
+        let token = refresh(&settings(url), "refresh".to_string()).unwrap();
+        let form = server.join().unwrap();
+
+        assert_eq!(form["grant_type"], "refresh_token");
+        assert_eq!(form["refresh_token"], "refresh");
+        assert_eq!(token.access_token, "new");
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh"));
+
+        let (url, server) = token_endpoint("127.0.0.1:0", "200 OK", r#"{"access_token":"new","refresh_token":"rotated"}"#);
+        let token = refresh(&settings(url), "refresh".to_string()).unwrap();
+        server.join().unwrap();
+        assert_eq!(token.refresh_token.as_deref(), Some("rotated"));
+    }
+
+    #[test]
+    fn endpoint_errors_are_classified() {
+        let (url, server) = token_endpoint(
+            "127.0.0.1:0",
+            "400 Bad Request",
+            r#"{"error":"invalid_grant","error_description":"Token has been revoked"}"#,
+        );
+        let error = refresh(&settings(url), "refresh".to_string()).err().unwrap();
+        server.join().unwrap();
+        assert!(!error.is_transient());
+        assert_eq!(error.to_string(), "token endpoint returned 400 (invalid_grant: Token has been revoked)");
+
+        let (url, server) = token_endpoint("127.0.0.1:0", "503 Service Unavailable", "down for maintenance");
+        let error = refresh(&settings(url), "refresh".to_string()).err().unwrap();
+        server.join().unwrap();
+        assert!(error.is_transient());
+
+        // Nothing listening
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let error = refresh(&settings(format!("http://127.0.0.1:{}/token", port)), "refresh".to_string())
+            .err()
+            .unwrap();
+        assert!(error.is_transient(), "{}", error);
+    }
+
+    #[test]
+    fn plain_http_is_only_allowed_on_loopback() {
+        let error = refresh(&settings("http://example.com/token".to_string()), "refresh".to_string())
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "token_url must use https");
+
+        // IPv6 loopback, which URLs write in brackets
+        if TcpListener::bind("[::1]:0").is_ok() {
+            let (url, server) = token_endpoint("[::1]:0", "200 OK", r#"{"access_token":"v6"}"#);
+            assert!(url.starts_with("http://[::1]:"));
+            let token = refresh(&settings(url), "refresh".to_string()).unwrap();
+            server.join().unwrap();
+            assert_eq!(token.access_token, "v6");
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Directory name under the platform's config and data directories.
+const APP_DIR: &str = "email-senderr";
+
+/// Overrides the settings file, like `--config`.
+pub const CONFIG_ENV: &str = "EMAIL_SENDERR_CONFIG";
+/// Overrides the data directory, like `--data-dir`.
+pub const DATA_DIR_ENV: &str = "EMAIL_SENDERR_DATA_DIR";
+
+/// Where the settings file was kept before it moved to the user's config
+/// directory; copied over once when nothing newer exists.
+const LEGACY_CONFIG: &str = "src/Settings.toml";
+
+/// Values from the example settings file, which mean it was never filled in.
+const PLACEHOLDERS: &[&str] = &["your-email@gmail.com", "your-app-password"];
+
+/// Data files that used to be saved next to wherever the app was started.
+const LEGACY_DATA_FILES: &[&str] = &["templates.json", "oauth_tokens.json"];
+
+pub const USAGE: &str = "Usage: email-senderr [--config <file>] [--data-dir <dir>]
+
+  --config <file>    Settings file (default: <config dir>/email-senderr/Settings.toml)
+  --data-dir <dir>   Directory for templates.json and saved sign-ins
+                     (default: <data dir>/email-senderr)
+
+The environment variables EMAIL_SENDERR_CONFIG and EMAIL_SENDERR_DATA_DIR
+are used when the flags are not given.";
+
+/// Why [`Paths::resolve`] didn't return paths.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ArgsError {
+    /// `--help` was given; print [`USAGE`] and exit successfully.
+    Help,
+    /// The arguments are wrong; the message ends with the usage text.
+    Invalid(String),
+}
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+static CONFIG_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where settings and data live, resolved once at startup.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub config_file: PathBuf,
+    pub data_dir: PathBuf,
+    /// Whether each path is the per-user default, the only place files from
+    /// older versions are migrated into.
+    default_config: bool,
+    default_data_dir: bool,
+}
+
+impl Paths {
+    /// Resolves the paths from command-line arguments (without the program
+    /// name), then environment variables, then the platform defaults.
+    pub fn resolve(args: impl IntoIterator<Item = String>) -> Result<Self, ArgsError> {
+        let mut config_flag = None;
+        let mut data_flag = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let slot = match flag.as_str() {
+                "--config" => &mut config_flag,
+                "--data-dir" => &mut data_flag,
+                "-h" | "--help" => return Err(ArgsError::Help),
+                _ => return Err(ArgsError::Invalid(format!("unknown argument '{}'\n\n{}", flag, USAGE))),
+            };
+            let value = inline
+                .or_else(|| args.next())
+                .ok_or_else(|| ArgsError::Invalid(format!("{} needs a value\n\n{}", flag, USAGE)))?;
+            *slot = Some(PathBuf::from(value));
+        }
+
+        let from_env = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+        let config_flag = config_flag.or_else(|| from_env(CONFIG_ENV));
+        let default_config = config_flag.is_none();
+        let config_file = match config_flag {
+            Some(path) => path,
+            None => config_dir()
+                .ok_or_else(|| ArgsError::Invalid("could not determine the user config directory; pass --config".to_string()))?
+                .join(APP_DIR)
+                .join("Settings.toml"),
+        };
+        let data_flag = data_flag.or_else(|| from_env(DATA_DIR_ENV));
+        let default_data_dir = data_flag.is_none();
+        let data_dir = match data_flag {
+            Some(path) => path,
+            None => data_dir()
+                .ok_or_else(|| ArgsError::Invalid("could not determine the user data directory; pass --data-dir".to_string()))?
+                .join(APP_DIR),
+        };
+        Ok(Self {
+            config_file,
+            data_dir,
+            default_config,
+            default_data_dir,
+        })
+    }
+
+    /// Creates the data directory and makes it, and the settings file's
+    /// directory, the ones relative paths are resolved against.
+    /// Returns status log lines for files migrated from older versions.
+    pub fn init(&self) -> std::io::Result<Vec<String>> {
+        std::fs::create_dir_all(&self.data_dir)?;
+        let _ = DATA_DIR.set(self.data_dir.clone());
+        let _ = CONFIG_DIR.set(self.config_file.parent().map(Path::to_path_buf).unwrap_or_default());
+        Ok(self.migrate_legacy_files())
+    }
+
+    /// Copies files from where older versions kept them into the per-user
+    /// directories, once: only while the new location has nothing yet. The
+    /// originals are left alone and never read again.
+    fn migrate_legacy_files(&self) -> Vec<String> {
+        let mut copies = Vec::new();
+        // The example file in the repository isn't a real setup
+        if self.default_config
+            && !self.config_file.exists()
+            && let Ok(data) = std::fs::read_to_string(LEGACY_CONFIG)
+            && !PLACEHOLDERS.iter().any(|p| data.contains(p))
+        {
+            copies.push((PathBuf::from(LEGACY_CONFIG), self.config_file.clone()));
+        }
+        if self.default_data_dir && !self.data_dir.join("templates.json").exists() {
+            for name in LEGACY_DATA_FILES {
+                let (from, to) = (PathBuf::from(name), self.data_dir.join(name));
+                if from.is_file() && !to.exists() {
+                    copies.push((from, to));
+                }
+            }
+        }
+
+        let mut log = Vec::new();
+        for (from, to) in copies {
+            let copied = std::fs::read(&from).and_then(|data| {
+                if let Some(parent) = to.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                write_private(&to, &data)
+            });
+            log.push(match copied {
+                Ok(()) => format!(
+                    "✓ Copied {} to {}; the old file is no longer used.",
+                    from.display(),
+                    to.display()
+                ),
+                Err(e) => format!("✗ Could not copy {} to {}: {}", from.display(), to.display(), e),
+            });
+        }
+        log
+    }
+}
+
+/// Path of a file in the data directory.
+pub fn data_file(name: &str) -> PathBuf {
+    DATA_DIR.get().map_or_else(|| PathBuf::from(name), |dir| dir.join(name))
+}
+
+/// A path from the settings file (`password_file`, a DKIM key, a `file`
+/// transport directory); relative ones are taken from the settings file's
+/// directory rather than wherever the app was started.
+pub fn settings_path(path: &Path) -> PathBuf {
+    resolve(CONFIG_DIR.get(), path)
+}
+
+/// A path from a template (attachments, inline images); relative ones are
+/// taken from the data directory.
+pub fn data_path(path: &Path) -> PathBuf {
+    resolve(DATA_DIR.get(), path)
+}
+
+fn resolve(base: Option<&PathBuf>, path: &Path) -> PathBuf {
+    match base {
+        Some(base) if path.is_relative() => base.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Like [`write_atomic`], but the file is readable by the current user only
+/// (on Unix), as for files holding credentials.
+pub fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    replace_file(path, data, Some(0o600))
+}
+
+/// Replaces `path` with `data` without ever leaving it half-written: the data
+/// goes to a temporary file next to it, is flushed to disk, then renamed over
+/// the original.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    replace_file(path, data, None)
+}
+
+/// Writes a temporary file created with `mode` (on Unix) and renames it over
+/// `path`, so the data is never readable with wider permissions on the way.
+fn replace_file(path: &Path, data: &[u8], mode: Option<u32>) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+    // A leftover temporary file would keep its own permissions
+    let _ = std::fs::remove_file(&temp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    let result = options
+        .open(&temp)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|()| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").filter(|v| !v.is_empty()).map(PathBuf::from)
+}
+
+/// `$XDG_CONFIG_HOME` (or `~/.config`) on Linux, `~/Library/Application
+/// Support` on macOS, `%APPDATA%` on Windows.
+fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library/Application Support"))
+    } else {
+        xdg_dir("XDG_CONFIG_HOME", ".config")
+    }
+}
+
+/// `$XDG_DATA_HOME` (or `~/.local/share`) on Linux, `~/Library/Application
+/// Support` on macOS, `%APPDATA%` on Windows.
+fn data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        home_dir().map(|home| home.join("Library/Application Support"))
+    } else {
+        xdg_dir("XDG_DATA_HOME", ".local/share")
+    }
+}
+
+/// An XDG base directory; relative values are invalid per the spec and ignored.
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| home_dir().map(|home| home.join(fallback)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_is_not_an_error_message() {
+        let args = |list: &[&str]| Paths::resolve(list.iter().map(|a| a.to_string()));
+        assert_eq!(args(&["--help"]).unwrap_err(), ArgsError::Help);
+        assert_eq!(args(&["-h"]).unwrap_err(), ArgsError::Help);
+        assert!(matches!(args(&["--bogus"]), Err(ArgsError::Invalid(e)) if e.starts_with("unknown argument '--bogus'")));
+        assert!(matches!(args(&["--config"]), Err(ArgsError::Invalid(e)) if e.starts_with("--config needs a value")));
+        let paths = args(&["--config=/tmp/s.toml", "--data-dir", "/tmp/data"]).unwrap();
+        assert_eq!(paths.config_file, PathBuf::from("/tmp/s.toml"));
+        assert_eq!(paths.data_dir, PathBuf::from("/tmp/data"));
+    }
+
+    #[test]
+    fn relative_paths_resolve_against_their_base() {
+        let base = PathBuf::from("/home/jane/.config/email-senderr");
+        assert_eq!(resolve(Some(&base), Path::new("keys/dkim.pem")), base.join("keys/dkim.pem"));
+        assert_eq!(resolve(Some(&base), Path::new("/etc/dkim.pem")), PathBuf::from("/etc/dkim.pem"));
+        // Before `init`, as in tests, paths are left alone
+        assert_eq!(resolve(None, Path::new("keys/dkim.pem")), PathBuf::from("keys/dkim.pem"));
+    }
+
+    #[test]
+    fn example_settings_are_not_migrated() {
+        // Tests run from the repository root, next to the example src/Settings.toml
+        assert!(Path::new(LEGACY_CONFIG).is_file());
+        let dir = std::env::temp_dir().join(format!("email-senderr-paths-{}", uuid::Uuid::new_v4()));
+        let paths = Paths {
+            config_file: dir.join("Settings.toml"),
+            data_dir: dir.clone(),
+            default_config: true,
+            default_data_dir: false,
+        };
+        assert!(paths.migrate_legacy_files().is_empty());
+        assert!(!paths.config_file.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private_files_are_replaced_with_owner_only_access() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("email-senderr-paths-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("Settings.toml");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(!dir.join("Settings.toml.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
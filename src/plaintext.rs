@@ -0,0 +1,410 @@
+/// Column at which plain-text output is wrapped.
+pub const WRAP_WIDTH: usize = 78;
+
+/// A run of text that starts on a new line, with the prefix for its first
+/// line (e.g. a bullet) and the indentation for wrapped continuation lines.
+#[derive(Debug, Default)]
+struct Paragraph {
+    first_prefix: String,
+    rest_prefix: String,
+    /// Text with `\n` for hard line breaks (`<br>`).
+    text: String,
+    blank_before: bool,
+}
+
+#[derive(Debug)]
+enum List {
+    Unordered,
+    Ordered(usize),
+}
+
+struct Converter {
+    paragraphs: Vec<Paragraph>,
+    current: Paragraph,
+    lists: Vec<List>,
+    links: Vec<(String, usize)>,
+    footnotes: Vec<String>,
+    blank_pending: bool,
+    skip_depth: usize,
+}
+
+impl Converter {
+    fn new() -> Self {
+        Self {
+            paragraphs: Vec::new(),
+            current: Paragraph::default(),
+            lists: Vec::new(),
+            links: Vec::new(),
+            footnotes: Vec::new(),
+            blank_pending: false,
+            skip_depth: 0,
+        }
+    }
+
+    /// Ends the current paragraph; `blank` asks for an empty line before the next one.
+    fn break_block(&mut self, blank: bool) {
+        let has_content =
+            !self.current.text.trim().is_empty() || !self.current.first_prefix.is_empty();
+        if has_content {
+            let done = std::mem::take(&mut self.current);
+            self.paragraphs.push(done);
+            self.blank_pending = false;
+        } else {
+            self.current.text.clear();
+        }
+        self.blank_pending |= blank;
+        self.current.blank_before = self.blank_pending;
+    }
+
+    fn push_text(&mut self, text: &str) {
+        if self.skip_depth > 0 {
+            return;
+        }
+        let decoded = decode_entities(text);
+        for c in decoded.chars() {
+            if c.is_ascii_whitespace() {
+                let at_line_start =
+                    self.current.text.is_empty() || self.current.text.ends_with(['\n', ' ']);
+                if !at_line_start {
+                    self.current.text.push(' ');
+                }
+            } else if c == '\u{a0}' {
+                self.current.text.push(' ');
+            } else {
+                self.current.text.push(c);
+            }
+        }
+    }
+
+    fn line_break(&mut self) {
+        let trimmed_len = self.current.text.trim_end_matches(' ').len();
+        self.current.text.truncate(trimmed_len);
+        self.current.text.push('\n');
+    }
+
+    fn start_list_item(&mut self) {
+        self.break_block(false);
+        let depth = self.lists.len().saturating_sub(1);
+        let indent = "  ".repeat(depth);
+        let marker = match self.lists.last_mut() {
+            Some(List::Ordered(n)) => {
+                *n += 1;
+                format!("{}. ", n)
+            }
+            _ => "- ".to_string(),
+        };
+        self.current.rest_prefix = format!("{}{}", indent, " ".repeat(marker.chars().count()));
+        self.current.first_prefix = format!("{}{}", indent, marker);
+    }
+
+    fn tag(&mut self, raw: &str) {
+        let raw = raw.trim().trim_end_matches('/').trim();
+        let (closing, body) = match raw.strip_prefix('/') {
+            Some(rest) => (true, rest.trim()),
+            None => (false, raw),
+        };
+        let name_end = body.find(|c: char| c.is_whitespace()).unwrap_or(body.len());
+        let name = body[..name_end].to_ascii_lowercase();
+
+        if matches!(name.as_str(), "script" | "style" | "head" | "title") {
+            if closing {
+                self.skip_depth = self.skip_depth.saturating_sub(1);
+            } else {
+                self.skip_depth += 1;
+            }
+            return;
+        }
+        if self.skip_depth > 0 {
+            return;
+        }
+
+        match (name.as_str(), closing) {
+            ("br", _) => self.line_break(),
+            ("p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" | "table", _) => {
+                self.break_block(true)
+            }
+            ("div" | "tr" | "section" | "article" | "header" | "footer", _) => self.break_block(false),
+            ("ul", false) => {
+                self.break_block(self.lists.is_empty());
+                self.lists.push(List::Unordered);
+            }
+            ("ol", false) => {
+                self.break_block(self.lists.is_empty());
+                self.lists.push(List::Ordered(0));
+            }
+            ("ul" | "ol", true) => {
+                self.lists.pop();
+                self.break_block(self.lists.is_empty());
+            }
+            ("li", false) => self.start_list_item(),
+            ("li", true) => self.break_block(false),
+            ("td" | "th", false)
+                if !self.current.text.is_empty() && !self.current.text.ends_with([' ', '\n']) =>
+            {
+                self.current.text.push(' ');
+            }
+            ("hr", _) => {
+                self.break_block(true);
+                self.current.text.push_str(&"-".repeat(20));
+                self.break_block(true);
+            }
+            ("img", false) => {
+                if let Some(alt) = attribute(body, "alt").filter(|a| !a.trim().is_empty()) {
+                    self.current.text.push_str(&format!("[{}]", alt.trim()));
+                }
+            }
+            ("a", false) => {
+                let href = attribute(body, "href").unwrap_or_default();
+                self.links.push((href, self.current.text.len()));
+            }
+            ("a", true) => {
+                if let Some((href, start)) = self.links.pop() {
+                    let text = self.current.text.get(start..).unwrap_or_default().trim().to_string();
+                    let target = href.strip_prefix("mailto:").unwrap_or(&href);
+                    let useful = !href.is_empty() && !href.starts_with('#') && !href.starts_with("cid:");
+                    if useful && text != target && text != href {
+                        if text.is_empty() {
+                            self.current.text.push_str(&href);
+                        } else {
+                            self.footnotes.push(href.clone());
+                            self.current.text.push_str(&format!(" [{}]", self.footnotes.len()));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.break_block(false);
+        let mut out = String::new();
+        for (i, para) in self.paragraphs.iter().enumerate() {
+            if i > 0 {
+                out.push('\n');
+                if para.blank_before {
+                    out.push('\n');
+                }
+            }
+            for (j, line) in para.text.split('\n').enumerate() {
+                if j > 0 {
+                    out.push('\n');
+                }
+                let prefix = if j == 0 { &para.first_prefix } else { &para.rest_prefix };
+                out.push_str(&wrap_line(line.trim(), prefix, &para.rest_prefix, WRAP_WIDTH));
+            }
+        }
+
+        let mut out = out.trim_matches('\n').to_string();
+        if !self.footnotes.is_empty() {
+            out.push_str("\n\n");
+            for (i, url) in self.footnotes.iter().enumerate() {
+                out.push_str(&format!("[{}] {}\n", i + 1, url));
+            }
+            out.pop();
+        }
+        out
+    }
+}
+
+/// Reads an attribute value from the inside of a start tag.
+pub fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search_from = 0;
+    while let Some(found) = lower[search_from..].find(name) {
+        let start = search_from + found;
+        search_from = start + name.len();
+        let preceded_ok = lower[..start].ends_with(|c: char| c.is_whitespace());
+        let rest = tag[start + name.len()..].trim_start();
+        let Some(value) = rest.strip_prefix('=').map(str::trim_start) else {
+            continue;
+        };
+        if !preceded_ok {
+            continue;
+        }
+        let raw = match value.chars().next() {
+            Some(q @ ('"' | '\'')) => value[1..].split(q).next().unwrap_or_default(),
+            _ => value.split(|c: char| c.is_whitespace()).next().unwrap_or_default(),
+        };
+        return Some(decode_entities(raw));
+    }
+    None
+}
+
+/// Word-wraps `line` to `width` columns. Words longer than the width (such as
+/// URLs) are kept whole on a line of their own.
+fn wrap_line(line: &str, first_prefix: &str, rest_prefix: &str, width: usize) -> String {
+    let mut out = String::from(first_prefix);
+    let mut column = first_prefix.chars().count();
+    let mut line_has_word = false;
+    for word in line.split(' ').filter(|w| !w.is_empty()) {
+        let len = word.chars().count();
+        if line_has_word && column + 1 + len > width {
+            out.push('\n');
+            out.push_str(rest_prefix);
+            column = rest_prefix.chars().count();
+            line_has_word = false;
+        }
+        if line_has_word {
+            out.push(' ');
+            column += 1;
+        }
+        out.push_str(word);
+        column += len;
+        line_has_word = true;
+    }
+    out.trim_end().to_string()
+}
+
+/// Converts an HTML email body into readable plain text: paragraphs and line
+/// breaks are kept, lists get bullets or numbers, link targets are listed as
+/// numbered footnotes, entities are decoded and lines wrap at 78 columns.
+pub fn html_to_text(html: &str) -> String {
+    let mut converter = Converter::new();
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        converter.push_text(&rest[..open]);
+        // A `<` that can't start a tag, as in `x < y`, is text
+        let starts_tag = rest[open + 1..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !starts_tag {
+            converter.push_text("<");
+            rest = &rest[open + 1..];
+            continue;
+        }
+        match rest[open..].find('>') {
+            Some(close) => {
+                let tag = &rest[open + 1..open + close];
+                if !tag.starts_with('!') {
+                    converter.tag(tag);
+                }
+                rest = &rest[open + close + 1..];
+            }
+            None => {
+                converter.push_text(&rest[open..]);
+                rest = "";
+            }
+        }
+    }
+    converter.push_text(rest);
+    converter.finish()
+}
+
+/// Decodes the HTML character references that show up in email bodies:
+/// the common named entities and decimal/hex numeric references.
+pub fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| decode_entity(&rest[1..=end]).map(|c| (c, end + 2)));
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(num) = name.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    let c = match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bull" => '•',
+        "euro" => '€',
+        _ => return None,
+    };
+    Some(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paragraphs_and_breaks() {
+        assert_eq!(
+            html_to_text("<p>Hi Jane,</p><p>First line<br>second line</p>"),
+            "Hi Jane,\n\nFirst line\nsecond line"
+        );
+        assert_eq!(html_to_text("<style>p { color: red }</style><div>One</div><div>Two</div>"), "One\nTwo");
+    }
+
+    #[test]
+    fn nested_lists() {
+        let html = "<ul><li>Fruit<ol><li>Apple</li><li>Pear</li></ol></li><li>Bread</li></ul>";
+        assert_eq!(html_to_text(html), "- Fruit\n  1. Apple\n  2. Pear\n- Bread");
+    }
+
+    #[test]
+    fn links_become_footnotes() {
+        let html = "<p>Read <a href=\"https://x.com/blog\">the blog</a> or mail \
+                    <a href=\"mailto:hi@x.com\">hi@x.com</a>. <a href=\"https://x.com\">https://x.com</a></p>";
+        assert_eq!(
+            html_to_text(html),
+            "Read the blog [1] or mail hi@x.com. https://x.com\n\n[1] https://x.com/blog"
+        );
+    }
+
+    #[test]
+    fn entities_are_decoded() {
+        assert_eq!(html_to_text("Fish &amp; chips &lt;3 &#8364;5 &#x263A;&nbsp;&copy;"), "Fish & chips <3 €5 ☺ ©");
+        assert_eq!(decode_entities("&bogus; &amp"), "&bogus; &amp");
+    }
+
+    #[test]
+    fn long_lines_wrap_with_their_prefix() {
+        let words = ["word"; 30].join(" ");
+        let text = html_to_text(&format!("<ul><li>{}</li></ul>", words));
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.len() > 1);
+        assert!(lines.iter().all(|line| line.chars().count() <= WRAP_WIDTH));
+        assert!(lines[0].starts_with("- word"));
+        assert!(lines[1..].iter().all(|line| line.starts_with("  word")));
+        // A URL longer than the width stays whole
+        let url = format!("https://x.com/{}", "a".repeat(100));
+        assert_eq!(html_to_text(&url), url);
+    }
+
+    #[test]
+    fn bare_angle_brackets_are_text() {
+        assert_eq!(html_to_text("x < y and 5 > 3"), "x < y and 5 > 3");
+        assert_eq!(html_to_text("<p>a <= b and <b>bold</b></p>"), "a <= b and bold");
+        assert_eq!(html_to_text("1 <2"), "1 <2");
+        assert_eq!(html_to_text("ends with <"), "ends with <");
+    }
+}
@@ -0,0 +1,268 @@
+use crate::engine::escape_html;
+use crate::template::BodyFormat;
+use std::ops::Range;
+
+/// Byte offset of the character at `char_index`, or the end of `text`.
+fn byte_index(text: &str, char_index: usize) -> usize {
+    text.char_indices()
+        .nth(char_index)
+        .map_or(text.len(), |(b, _)| b)
+}
+
+fn char_count(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Clamps a character range to `text` and puts its ends in order.
+fn normalize(text: &str, selection: Range<usize>) -> Range<usize> {
+    let len = char_count(text);
+    let (a, b) = (selection.start.min(len), selection.end.min(len));
+    a.min(b)..a.max(b)
+}
+
+/// Wraps the selection in `open`/`close`, or unwraps it when the selection
+/// already starts and ends with them or sits directly between them. Ranges
+/// are in characters, as egui's cursors are; returns the new selection.
+pub fn toggle_wrap(text: &mut String, selection: Range<usize>, open: &str, close: &str) -> Range<usize> {
+    let selection = normalize(text, selection);
+    let start = byte_index(text, selection.start);
+    let end = byte_index(text, selection.end);
+    let open_chars = char_count(open);
+    let close_chars = char_count(close);
+
+    let selected = &text[start..end];
+    if selected.len() >= open.len() + close.len()
+        && selected.starts_with(open)
+        && selected.ends_with(close)
+    {
+        text.replace_range(end - close.len()..end, "");
+        text.replace_range(start..start + open.len(), "");
+        return selection.start..selection.end - open_chars - close_chars;
+    }
+
+    if text[..start].ends_with(open) && text[end..].starts_with(close) {
+        text.replace_range(end..end + close.len(), "");
+        text.replace_range(start - open.len()..start, "");
+        return selection.start - open_chars..selection.end - open_chars;
+    }
+
+    text.insert_str(end, close);
+    text.insert_str(start, open);
+    selection.start + open_chars..selection.end + open_chars
+}
+
+/// Replaces the selection with a link to `url`, using the selected text as
+/// the link text (or the URL itself when nothing is selected). Returns the
+/// cursor position just after the link.
+pub fn insert_link(text: &mut String, selection: Range<usize>, url: &str, format: BodyFormat) -> Range<usize> {
+    let selection = normalize(text, selection);
+    let start = byte_index(text, selection.start);
+    let end = byte_index(text, selection.end);
+    let label = match text[start..end].trim() {
+        "" => url.to_string(),
+        selected => selected.to_string(),
+    };
+
+    let link = match format {
+        BodyFormat::Html => format!("<a href=\"{}\">{}</a>", escape_html(url), label),
+        BodyFormat::Markdown if url.contains([' ', '(', ')']) => format!("[{}](<{}>)", label, url),
+        BodyFormat::Markdown => format!("[{}]({})", label, url),
+    };
+    text.replace_range(start..end, &link);
+    let cursor = selection.start + char_count(&link);
+    cursor..cursor
+}
+
+/// Replaces the selection with a reference to the inline image `cid`.
+/// Returns the cursor position just after it.
+pub fn insert_image(text: &mut String, selection: Range<usize>, cid: &str, alt: &str, format: BodyFormat) -> Range<usize> {
+    let selection = normalize(text, selection);
+    let start = byte_index(text, selection.start);
+    let end = byte_index(text, selection.end);
+    let image = match format {
+        BodyFormat::Html => format!("<img src=\"cid:{}\" alt=\"{}\">", cid, escape_html(alt)),
+        BodyFormat::Markdown => format!("![{}](cid:{})", alt, cid),
+    };
+    text.replace_range(start..end, &image);
+    let cursor = selection.start + char_count(&image);
+    cursor..cursor
+}
+
+/// Turns the lines touched by the selection into a bulleted list, or back
+/// into plain lines if they already are one. Returns the selection covering
+/// the rewritten lines.
+pub fn toggle_list(text: &mut String, selection: Range<usize>, format: BodyFormat) -> Range<usize> {
+    let selection = normalize(text, selection);
+    let start = byte_index(text, selection.start);
+    let mut end = byte_index(text, selection.end);
+    // A selection ending at the start of a line doesn't include that line.
+    if end > start && text[..end].ends_with('\n') {
+        end -= 1;
+    }
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[end..].find('\n').map_or(text.len(), |i| end + i);
+    let block = &text[line_start..line_end];
+    let first = char_count(&text[..line_start]);
+
+    // On an empty line, start a list with the cursor in its first item.
+    if block.trim().is_empty() {
+        let (list, cursor) = match format {
+            BodyFormat::Html => ("<ul><li></li></ul>", "<ul><li>".len()),
+            BodyFormat::Markdown => ("- ", 2),
+        };
+        text.replace_range(line_start..line_end, list);
+        return first + cursor..first + cursor;
+    }
+
+    let items = |block: &str| -> Vec<String> {
+        block
+            .lines()
+            .map(|line| {
+                let line = line.trim();
+                line.strip_prefix("• ")
+                    .or_else(|| line.strip_prefix("- "))
+                    .unwrap_or(line)
+                    .to_string()
+            })
+            .filter(|line| !line.is_empty())
+            .collect()
+    };
+
+    let replacement = match format {
+        BodyFormat::Html => {
+            let trimmed = block.trim();
+            match trimmed
+                .strip_prefix("<ul>")
+                .and_then(|rest| rest.strip_suffix("</ul>"))
+            {
+                Some(inner) => inner
+                    .split("</li>")
+                    .map(|item| item.trim().trim_start_matches("<li>").trim())
+                    .filter(|item| !item.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                // HTML bodies turn newlines into <br>, so the list is kept on one line.
+                None => format!(
+                    "<ul>{}</ul>",
+                    items(block)
+                        .iter()
+                        .map(|item| format!("<li>{}</li>", item))
+                        .collect::<String>()
+                ),
+            }
+        }
+        BodyFormat::Markdown => {
+            let is_list = block
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .all(|line| line.trim_start().starts_with("- "));
+            if is_list {
+                items(block).join("\n")
+            } else {
+                items(block)
+                    .iter()
+                    .map(|item| format!("- {}", item))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        }
+    };
+
+    text.replace_range(line_start..line_end, &replacement);
+    first..first + char_count(&replacement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(
+        text: &str,
+        selection: Range<usize>,
+        edit: impl FnOnce(&mut String, Range<usize>) -> Range<usize>,
+    ) -> (String, Range<usize>) {
+        let mut text = text.to_string();
+        let selection = edit(&mut text, selection);
+        (text, selection)
+    }
+
+    fn bold(text: &mut String, selection: Range<usize>) -> Range<usize> {
+        toggle_wrap(text, selection, "**", "**")
+    }
+
+    #[test]
+    fn wrap_toggles_off_around_or_inside_the_markers() {
+        assert_eq!(apply("say hello", 4..9, bold), ("say **hello**".to_string(), 6..11));
+        // Selection including the markers, or just the text between them
+        assert_eq!(apply("say **hello**", 4..13, bold), ("say hello".to_string(), 4..9));
+        assert_eq!(apply("say **hello**", 6..11, bold), ("say hello".to_string(), 4..9));
+        // Only the marker is checked, not what else is around it
+        assert_eq!(apply("***x***", 3..4, bold), ("*x*".to_string(), 1..2));
+    }
+
+    #[test]
+    fn wrap_at_text_boundaries() {
+        let tags = |text: &mut String, selection| toggle_wrap(text, selection, "<b>", "</b>");
+        assert_eq!(apply("hi", 0..2, tags), ("<b>hi</b>".to_string(), 3..5));
+        assert_eq!(apply("<b>hi</b>", 3..5, tags), ("hi".to_string(), 0..2));
+        assert_eq!(apply("<b>hi</b>", 0..9, tags), ("hi".to_string(), 0..2));
+        // Reversed and out-of-range selections are clamped
+        assert_eq!(apply("hi", Range { start: 99, end: 0 }, tags), ("<b>hi</b>".to_string(), 3..5));
+        assert_eq!(apply("hi", 2..2, tags), ("hi<b></b>".to_string(), 5..5));
+    }
+
+    #[test]
+    fn wrap_counts_characters_not_bytes() {
+        let em = |text: &mut String, selection| toggle_wrap(text, selection, "_", "_");
+        assert_eq!(apply("naïve café", 6..10, em), ("naïve _café_".to_string(), 7..11));
+        assert_eq!(apply("naïve _café_", 7..11, em), ("naïve café".to_string(), 6..10));
+        assert_eq!(apply("naïve", 2..3, bold), ("na**ï**ve".to_string(), 4..5));
+        assert_eq!(apply("👋 hi", 0..1, bold), ("**👋** hi".to_string(), 2..3));
+    }
+
+    #[test]
+    fn list_covers_every_line_the_selection_touches() {
+        let markdown = |text: &mut String, selection| toggle_list(text, selection, BodyFormat::Markdown);
+        let text = "intro\nfirst item\nsecond item\noutro";
+        let listed = "intro\n- first item\n- second item\noutro";
+        assert_eq!(apply(text, 9..20, markdown), (listed.to_string(), 6..32));
+        assert_eq!(apply(listed, 6..32, markdown), (text.to_string(), 6..28));
+        // Ending at the start of a line leaves that line alone
+        assert_eq!(
+            apply(text, 6..17, markdown),
+            ("intro\n- first item\nsecond item\noutro".to_string(), 6..18)
+        );
+        assert_eq!(apply("ünï\ncode", 1..2, markdown), ("- ünï\ncode".to_string(), 0..5));
+
+        let html = |text: &mut String, selection| toggle_list(text, selection, BodyFormat::Html);
+        assert_eq!(apply("a\nb", 0..1, html), ("<ul><li>a</li></ul>\nb".to_string(), 0..19));
+        assert_eq!(apply("a\nb", 1..3, html), ("<ul><li>a</li><li>b</li></ul>".to_string(), 0..29));
+        assert_eq!(apply("<ul><li>a</li><li>b</li></ul>", 10..12, html), ("a\nb".to_string(), 0..3));
+        assert_eq!(apply("x\n\ny", 2..2, html), ("x\n<ul><li></li></ul>\ny".to_string(), 10..10));
+    }
+
+    #[test]
+    fn links_use_the_selection_as_their_text() {
+        let markdown = |url: &'static str| {
+            move |text: &mut String, selection| insert_link(text, selection, url, BodyFormat::Markdown)
+        };
+        assert_eq!(
+            apply("see docs here", 4..8, markdown("https://x.io")),
+            ("see [docs](https://x.io) here".to_string(), 24..24)
+        );
+        assert_eq!(
+            apply("go ", 3..3, markdown("https://a.b/c d")),
+            ("go [https://a.b/c d](<https://a.b/c d>)".to_string(), 39..39)
+        );
+        assert_eq!(apply("docs", 0..99, markdown("/d")), ("[docs](/d)".to_string(), 10..10));
+
+        let html = |text: &mut String, selection| {
+            insert_link(text, selection, "https://x.io/?a=1&b=\"2\"", BodyFormat::Html)
+        };
+        let link = "<a href=\"https://x.io/?a=1&amp;b=&quot;2&quot;\">☕</a>";
+        assert_eq!(
+            apply("café ☕!", 5..6, html),
+            (format!("café {}!", link), 5 + link.chars().count()..5 + link.chars().count())
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Keyring service under which passwords are looked up, with the profile's
+/// `username` as the account.
+pub const KEYRING_SERVICE: &str = "email-senderr";
+
+/// A password or other credential. It has no `Display`, and its `Debug`
+/// output is redacted, so it can't end up in an error or the status log.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_empty() { "Secret(\"\")" } else { "Secret(***)" })
+    }
+}
+
+/// Where a profile's SMTP password comes from.
+#[derive(Debug, Clone)]
+pub enum PasswordSource {
+    /// Written directly in `Settings.toml`.
+    Plain(Secret),
+    /// An environment variable.
+    Env(String),
+    /// A file that only the current user can read.
+    File(PathBuf),
+    /// A shell command that prints the password, e.g. `pass show smtp/work`.
+    Command(String),
+    /// The OS keyring: Secret Service on Linux, Keychain on macOS,
+    /// Credential Locker on Windows.
+    Keyring,
+}
+
+impl PasswordSource {
+    /// Reads the password. Errors say where it was looked for, never what was found.
+    pub fn read(&self, account: &str) -> Result<Secret, String> {
+        let secret = match self {
+            PasswordSource::Plain(secret) => secret.clone(),
+            PasswordSource::Env(name) => std::env::var(name)
+                .map(Secret)
+                .map_err(|_| format!("environment variable {} is not set", name))?,
+            PasswordSource::File(path) => read_file(path)?,
+            PasswordSource::Command(command) => {
+                let mut process = if cfg!(windows) {
+                    let mut process = Command::new("cmd");
+                    process.args(["/C", command]);
+                    process
+                } else {
+                    let mut process = Command::new("sh");
+                    process.args(["-c", command]);
+                    process
+                };
+                run(&mut process, "password_command")?
+            }
+            PasswordSource::Keyring => run(&mut keyring_lookup(account), "keyring lookup")
+                .map_err(|e| {
+                    format!(
+                        "{} (service '{}', account '{}')",
+                        e, KEYRING_SERVICE, account
+                    )
+                })?,
+        };
+        if secret.is_empty() {
+            return Err(format!("password from {} is empty", self.describe()));
+        }
+        Ok(secret)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            PasswordSource::Plain(_) => "Settings.toml".to_string(),
+            PasswordSource::Env(name) => format!("environment variable {}", name),
+            PasswordSource::File(path) => path.display().to_string(),
+            PasswordSource::Command(_) => "password_command".to_string(),
+            PasswordSource::Keyring => "the keyring".to_string(),
+        }
+    }
+}
+
+/// Reads a password file, refusing ones other users can read, as ssh does
+/// for private keys.
+fn read_file(path: &Path) -> Result<Secret, String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)
+            .map_err(|e| format!("password file {}: {}", path.display(), e))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "password file {} is accessible by other users; run `chmod 600 {}`",
+                path.display(),
+                path.display()
+            ));
+        }
+    }
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| format!("password file {}: {}", path.display(), e))?;
+    Ok(Secret(strip_line_ending(&contents)))
+}
+
+/// Runs a command and takes its standard output as the secret. Only the exit
+/// status and stderr are reported on failure.
+fn run(command: &mut Command, what: &str) -> Result<Secret, String> {
+    let output = command
+        .output()
+        .map_err(|e| {
+            format!(
+                "{} could not run {}: {}",
+                what,
+                command.get_program().to_string_lossy(),
+                e
+            )
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or("").trim();
+        return Err(format!("{} failed ({}) {}", what, output.status, reason)
+            .trim_end()
+            .to_string());
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| format!("{} printed something that isn't UTF-8", what))?;
+    Ok(Secret(strip_line_ending(&stdout)))
+}
+
+/// Drops the trailing newline editors and `echo` add; other whitespace may
+/// be part of the password.
+fn strip_line_ending(text: &str) -> String {
+    text.strip_suffix('\n')
+        .map(|t| t.strip_suffix('\r').unwrap_or(t))
+        .unwrap_or(text)
+        .to_string()
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_lookup(account: &str) -> Command {
+    let mut command = Command::new("security");
+    command.args(["find-generic-password", "-s", KEYRING_SERVICE, "-a", account, "-w"]);
+    command
+}
+
+#[cfg(windows)]
+fn keyring_lookup(account: &str) -> Command {
+    let quote = |s: &str| format!("'{}'", s.replace('\'', "''"));
+    let script = format!(
+        "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime]; \
+         $c = (New-Object Windows.Security.Credentials.PasswordVault).Retrieve({}, {}); \
+         $c.RetrievePassword(); [Console]::Out.Write($c.Password)",
+        quote(KEYRING_SERVICE),
+        quote(account)
+    );
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+    command
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn keyring_lookup(account: &str) -> Command {
+    let mut command = Command::new("secret-tool");
+    command.args(["lookup", "service", KEYRING_SERVICE, "account", account]);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("email-senderr-secret-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn file_passwords_lose_one_line_ending() {
+        for (contents, expected) in [("hunter2\n", "hunter2"), ("hunter2 \r\n", "hunter2 "), ("two\n\n", "two\n")] {
+            let path = temp_file(contents);
+            let secret = PasswordSource::File(path.clone()).read("me").unwrap();
+            assert_eq!(secret.expose(), expected);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn password_files_others_can_read_are_refused() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_file("hunter2\n");
+        for mode in [0o640, 0o604] {
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            let error = PasswordSource::File(path.clone()).read("me").unwrap_err();
+            assert!(error.contains("accessible by other users"), "{}", error);
+            assert!(!error.contains("hunter2"));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_output_loses_one_line_ending() {
+        let source = PasswordSource::Command("printf 'hunter2\\r\\n'".to_string());
+        assert_eq!(source.read("me").unwrap().expose(), "hunter2");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failing_commands_report_stderr_not_stdout() {
+        let source = PasswordSource::Command("echo hunter2; echo 'no such entry' >&2; exit 3".to_string());
+        assert_eq!(
+            source.read("me").unwrap_err(),
+            "password_command failed (exit status: 3) no such entry"
+        );
+    }
+
+    #[test]
+    fn debug_output_is_redacted() {
+        assert_eq!(format!("{:?}", Secret::new("hunter2")), "Secret(***)");
+        assert_eq!(format!("{:?}", Secret::default()), "Secret(\"\")");
+        let source = format!("{:?}", PasswordSource::Plain(Secret::new("hunter2")));
+        assert!(!source.contains("hunter2"), "{}", source);
+    }
+}
@@ -1,12 +1,161 @@
+use crate::engine::{self, Escape};
+use crate::markdown;
+use crate::paths;
+use crate::plaintext::html_to_text;
+use lettre::message::{Mailbox, Mailboxes};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+/// Delivery state of one recipient, persisted so an interrupted campaign can
+/// be resumed without sending duplicates. Timestamps are Unix seconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "lowercase")]
+pub enum SendStatus {
+    #[default]
+    Pending,
+    Sent { at: u64, message_id: String },
+    Failed { at: u64, error: String },
+}
+
+impl SendStatus {
+    pub fn is_sent(&self) -> bool {
+        matches!(self, SendStatus::Sent { .. })
+    }
+}
+
+/// How the template body is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyFormat {
+    /// HTML tags with plain newlines as line breaks.
+    #[default]
+    Html,
+    /// Markdown, converted to HTML when the email is built.
+    Markdown,
+}
+
+impl BodyFormat {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BodyFormat::Html => "HTML",
+            BodyFormat::Markdown => "Markdown",
+        }
+    }
+}
+
+/// Parses a comma-separated address list such as
+/// `jane@x.com, "Doe, John" <john@x.com>`. Blank text is an empty list.
+pub fn parse_address_list(text: &str) -> Result<Vec<Mailbox>, String> {
+    if text.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    text.parse::<Mailboxes>()
+        .map(|list| list.into_iter().collect())
+        .map_err(|e| format!("invalid address list '{}': {}", text.trim(), e))
+}
+
+impl Recipient {
+    /// The `To` mailboxes, with `display_name` applied to the first address
+    /// if it has no name of its own.
+    pub fn to_mailboxes(&self) -> Result<Vec<Mailbox>, String> {
+        let mut to = parse_address_list(&self.email)?;
+        let Some(first) = to.first_mut() else {
+            return Err("no email address".to_string());
+        };
+        if first.name.is_none() && !self.display_name.trim().is_empty() {
+            first.name = Some(self.display_name.trim().to_string());
+        }
+        Ok(to)
+    }
+
+    /// The first `To` address, lowercased; used to spot duplicate recipients.
+    pub fn primary_address(&self) -> String {
+        self.to_mailboxes()
+            .ok()
+            .and_then(|to| to.first().map(|m| m.email.to_string()))
+            .unwrap_or_else(|| self.email.trim().to_string())
+            .to_lowercase()
+    }
+}
+
+/// An extra header added to every message; the value may use placeholders.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CustomHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Headers set by the app itself, which custom headers may not replace.
+const RESERVED_HEADERS: &[&str] = &[
+    "from",
+    "sender",
+    "to",
+    "cc",
+    "bcc",
+    "subject",
+    "reply-to",
+    "date",
+    "message-id",
+    "mime-version",
+    "content-type",
+    "content-transfer-encoding",
+    "list-unsubscribe",
+    "list-unsubscribe-post",
+];
+
+/// `List-Unsubscribe` settings. Both targets may use placeholders to give each
+/// recipient their own link, e.g. `https://x.com/unsubscribe?id={customer_id}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Unsubscribe {
+    /// HTTP(S) unsubscribe link.
+    #[serde(default)]
+    pub url: String,
+    /// Address to email to unsubscribe, optionally with `?subject=...`.
+    #[serde(default)]
+    pub mailto: String,
+    /// Adds `List-Unsubscribe-Post: List-Unsubscribe=One-Click` (RFC 8058),
+    /// letting mailbox providers unsubscribe with a POST to `url`.
+    #[serde(default)]
+    pub one_click: bool,
+}
+
+/// An image embedded in the email and shown in the body through a
+/// `cid:<cid>` reference, e.g. `<img src="cid:logo">`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InlineImage {
+    pub cid: String,
+    pub path: PathBuf,
+}
+
+impl InlineImage {
+    /// The image file, with a relative `path` taken from the data directory.
+    pub fn file(&self) -> PathBuf {
+        paths::data_path(&self.path)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Recipient {
+    /// One or more comma-separated `To` addresses, e.g.
+    /// `jane@x.com, "Doe, John" <john@x.com>`.
     pub email: String,
+    /// Display name for the first `To` address when it doesn't carry one.
+    #[serde(default)]
+    pub display_name: String,
+    /// Comma-separated `Cc` addresses for this recipient.
+    #[serde(default)]
+    pub cc: String,
+    /// Comma-separated `Bcc` addresses for this recipient.
+    #[serde(default)]
+    pub bcc: String,
     pub args: HashMap<String, String>,
+    /// Files sent to this recipient only, in addition to the template's.
+    #[serde(default)]
+    pub attachments: Vec<PathBuf>,
+    #[serde(default)]
+    pub status: SendStatus,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,8 +164,29 @@ pub struct EmailTemplate {
     pub name: String,
     pub subject: String,
     pub body: String,
+    #[serde(default)]
+    pub body_format: BodyFormat,
+    /// Hand-written text/plain alternative. When `None`, it is generated from
+    /// the HTML body.
+    #[serde(default)]
+    pub plain_body: Option<String>,
     pub attachment_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub inline_images: Vec<InlineImage>,
     pub recipients: Vec<Recipient>,
+    #[serde(default)]
+    pub headers: Vec<CustomHeader>,
+    #[serde(default)]
+    pub unsubscribe: Unsubscribe,
+    /// Comma-separated addresses copied on every message, e.g. a CRM archive.
+    #[serde(default)]
+    pub cc: String,
+    /// Comma-separated addresses blind-copied on every message.
+    #[serde(default)]
+    pub bcc: String,
+    /// Name of the sender profile to send from; `None` uses the first profile.
+    #[serde(default)]
+    pub sender_profile: Option<String>,
 }
 
 impl EmailTemplate {
@@ -26,64 +196,597 @@ impl EmailTemplate {
             name,
             subject: String::new(),
             body: String::new(),
+            body_format: BodyFormat::default(),
+            plain_body: None,
             attachment_paths: Vec::new(),
+            inline_images: Vec::new(),
             recipients: Vec::new(),
+            headers: Vec::new(),
+            unsubscribe: Unsubscribe::default(),
+            cc: String::new(),
+            bcc: String::new(),
+            sender_profile: None,
         }
     }
 
-    /// Replace all `{key}` placeholders with recipient arg values.
-    pub fn render_text(&self, text: &str, recipient: &Recipient) -> String {
-        let mut result = text.to_string();
-        for (key, value) in &recipient.args {
-            let placeholder = format!("{{{}}}", key);
-            result = result.replace(&placeholder, value);
+    /// A copy under a new id and name, ready for a new campaign: every
+    /// recipient is pending again, so nothing is skipped as already sent.
+    pub fn duplicate(&self) -> Self {
+        let mut copy = self.clone();
+        copy.id = Uuid::new_v4().to_string();
+        copy.name = format!("{} - copy", self.name);
+        for recipient in &mut copy.recipients {
+            recipient.status = SendStatus::Pending;
         }
-        result
+        copy
+    }
+
+    /// Renders template syntax (see [`engine::render`]) with recipient arg
+    /// values inserted verbatim.
+    pub fn render_text(&self, text: &str, recipient: &Recipient) -> String {
+        engine::render(text, &recipient.args, Escape::None).text
+    }
+
+    /// Like [`Self::render_text`], but percent-encodes values so they can't
+    /// break out of the URL they're inserted into.
+    fn render_url(&self, text: &str, recipient: &Recipient) -> String {
+        engine::render(text, &recipient.args, Escape::Url).text
+    }
+
+    /// Indices of recipients that haven't been sent to yet (pending or failed).
+    pub fn unsent_recipients(&self) -> Vec<usize> {
+        self.recipients
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| !r.status.is_sent())
+            .map(|(i, _)| i)
+            .collect()
     }
 
     pub fn render_subject(&self, recipient: &Recipient) -> String {
         self.render_text(&self.subject, recipient)
     }
 
-    pub fn render_body(&self, recipient: &Recipient) -> String {
-        self.render_text(&self.body, recipient)
+    /// Renders the body for the HTML part: arg values are escaped unless
+    /// marked `{key:raw}`, while the template's own markup passes through.
+    pub fn render_body_html(&self, recipient: &Recipient) -> String {
+        let escape = match self.body_format {
+            BodyFormat::Html => Escape::Html,
+            BodyFormat::Markdown => Escape::Markdown,
+        };
+        self.body_to_html(&engine::render(&self.body, &recipient.args, escape).text)
+    }
+
+    /// Converts body source in this template's format to HTML. In HTML bodies,
+    /// plain newlines become `<br>` so line breaks are preserved.
+    pub fn body_to_html(&self, source: &str) -> String {
+        match self.body_format {
+            BodyFormat::Html => source.replace('\n', "<br>"),
+            BodyFormat::Markdown => markdown::to_html(source),
+        }
+    }
+
+    /// Renders the text/plain part: the hand-written plain body if there is
+    /// one, otherwise a conversion of the HTML body.
+    pub fn render_plain_body(&self, recipient: &Recipient) -> String {
+        match &self.plain_body {
+            Some(plain) => self.render_text(plain, recipient),
+            None => html_to_text(&self.render_body_html(recipient)),
+        }
+    }
+
+    /// The files attached to this recipient's message: the template's
+    /// attachments, with placeholders in their paths filled in (e.g.
+    /// `invoices/{customer_id}.pdf`), followed by the recipient's own.
+    /// Relative paths are taken from the data directory. Paths with a
+    /// placeholder the recipient has no value for are left out; see
+    /// [`Self::unresolved_attachment_paths`].
+    pub fn attachments_for(&self, recipient: &Recipient) -> Vec<PathBuf> {
+        self.attachment_paths
+            .iter()
+            .filter_map(|path| match path.to_str() {
+                Some(pattern) if pattern.contains('{') => {
+                    let rendered = engine::render(pattern, &recipient.args, Escape::None);
+                    rendered.missing.is_empty().then(|| PathBuf::from(rendered.text))
+                }
+                _ => Some(path.clone()),
+            })
+            .chain(recipient.attachments.iter().cloned())
+            .map(|path| paths::data_path(&path))
+            .collect()
+    }
+
+    /// Template attachment paths with placeholders this recipient has no
+    /// value for, e.g. `invoices/{id}.pdf` without an `id`. Filling them in
+    /// would name the wrong file, so each is described instead.
+    pub fn unresolved_attachment_paths(&self, recipient: &Recipient) -> Vec<String> {
+        self.attachment_paths
+            .iter()
+            .filter_map(|path| {
+                let pattern = path.to_str()?;
+                let missing = engine::render(pattern, &recipient.args, Escape::None).missing;
+                (!missing.is_empty()).then(|| {
+                    format!(
+                        "attachment {} has no value for {}",
+                        pattern,
+                        missing
+                            .iter()
+                            .map(|k| format!("{{{}}}", k))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Problems with the custom header names and unsubscribe settings that
+    /// don't depend on the recipient.
+    pub fn header_issues(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        for header in &self.headers {
+            let name = header.name.trim();
+            if name.is_empty() {
+                if !header.value.trim().is_empty() {
+                    issues.push("custom header with a value but no name".to_string());
+                }
+            } else if !name.chars().all(|c| c.is_ascii_graphic() && c != ':') {
+                issues.push(format!("invalid header name '{}'", name));
+            } else if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+                issues.push(format!("header '{}' is set by the app and can't be overridden", name));
+            }
+        }
+        if self.unsubscribe.one_click && self.unsubscribe.url.trim().is_empty() {
+            issues.push("one-click unsubscribe needs an unsubscribe URL".to_string());
+        }
+        issues
     }
 
-    /// Extract all placeholder keys like `{name}` from body and subject.
+    /// Renders the custom headers and `List-Unsubscribe` headers for this
+    /// recipient as `(name, value)` pairs.
+    pub fn render_headers(&self, recipient: &Recipient) -> Result<Vec<(String, String)>, String> {
+        if let Some(issue) = self.header_issues().into_iter().next() {
+            return Err(issue);
+        }
+        let mut headers = Vec::new();
+        for header in self.headers.iter().filter(|h| !h.name.trim().is_empty()) {
+            let value = self.render_text(&header.value, recipient);
+            if value.contains(['\r', '\n']) {
+                return Err(format!("header '{}' contains a line break", header.name.trim()));
+            }
+            headers.push((header.name.trim().to_string(), value.trim().to_string()));
+        }
+
+        let mut targets = Vec::new();
+        let url = self.render_url(self.unsubscribe.url.trim(), recipient);
+        if !url.is_empty() {
+            if self.unsubscribe.one_click && !url.starts_with("https://") {
+                return Err(format!("one-click unsubscribe URL must use https: {}", url));
+            }
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(format!("unsubscribe URL must start with http(s)://: {}", url));
+            }
+            targets.push(url);
+        }
+        let mailto = self.render_url(self.unsubscribe.mailto.trim(), recipient);
+        if !mailto.is_empty() {
+            match mailto.strip_prefix("mailto:") {
+                Some(_) => targets.push(mailto),
+                None => targets.push(format!("mailto:{}", mailto)),
+            }
+        }
+        if let Some(bad) = targets.iter().find(|t| t.contains(['<', '>', ' ', '\r', '\n'])) {
+            return Err(format!("unsubscribe target '{}' contains invalid characters", bad));
+        }
+        if !targets.is_empty() {
+            let value = targets
+                .iter()
+                .map(|t| format!("<{}>", t))
+                .collect::<Vec<_>>()
+                .join(", ");
+            headers.push(("List-Unsubscribe".to_string(), value));
+            if self.unsubscribe.one_click {
+                headers.push((
+                    "List-Unsubscribe-Post".to_string(),
+                    "List-Unsubscribe=One-Click".to_string(),
+                ));
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Adds an inline image and returns its Content-ID, derived from the file
+    /// name and made unique within the template.
+    pub fn add_inline_image(&mut self, path: PathBuf) -> String {
+        let stem: String = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if is_cid_char(c) { c } else { '-' })
+            .collect();
+        let base = if stem.is_empty() { "image".to_string() } else { stem };
+        let mut cid = base.clone();
+        let mut n = 2;
+        while self.inline_images.iter().any(|img| img.cid == cid) {
+            cid = format!("{}-{}", base, n);
+            n += 1;
+        }
+        self.inline_images.push(InlineImage {
+            cid: cid.clone(),
+            path,
+        });
+        cid
+    }
+
+    /// The inline images that `html` refers to by `cid:`.
+    pub fn referenced_images(&self, html: &str) -> Vec<&InlineImage> {
+        self.inline_images
+            .iter()
+            .filter(|img| references_cid(html, &img.cid))
+            .collect()
+    }
+
+    /// `cid:` references in the body with no matching inline image.
+    pub fn unknown_image_references(&self) -> Vec<String> {
+        let mut unknown: Vec<String> = Vec::new();
+        for (i, _) in self.body.match_indices("cid:") {
+            let cid: String = self.body[i + 4..].chars().take_while(|&c| is_cid_char(c)).collect();
+            if !self.inline_images.iter().any(|img| img.cid == cid) && !unknown.contains(&cid) {
+                unknown.push(cid);
+            }
+        }
+        unknown
+    }
+
+    /// Extract all argument keys referenced from subject, bodies, attachment
+    /// paths and headers, including those used only in `{#if}` conditions and
+    /// `{#each}` loops.
     pub fn extract_placeholders(&self) -> Vec<String> {
         let mut placeholders = Vec::new();
-        let combined = format!("{} {}", self.subject, self.body);
-        let mut chars = combined.chars().peekable();
-        while let Some(c) = chars.next() {
-            if c == '{' {
-                let mut key = String::new();
-                for inner in chars.by_ref() {
-                    if inner == '}' {
-                        break;
-                    }
-                    key.push(inner);
-                }
-                if !key.is_empty() && !placeholders.contains(&key) {
+        for text in self.template_texts() {
+            for key in engine::referenced_keys(text) {
+                if !placeholders.contains(&key) {
                     placeholders.push(key);
                 }
             }
         }
         placeholders
     }
+
+    /// Subject, body, hand-written plain body (if any), attachment paths,
+    /// header values and unsubscribe targets.
+    fn template_texts(&self) -> impl Iterator<Item = &str> {
+        [Some(&self.subject), Some(&self.body), self.plain_body.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(self.attachment_paths.iter().filter_map(|p| p.to_str()))
+            .chain(self.headers.iter().map(|h| h.value.as_str()))
+            .chain([self.unsubscribe.url.as_str(), self.unsubscribe.mailto.as_str()])
+    }
+
+    /// Placeholders that would be left unfilled or blank for this recipient.
+    pub fn unresolved_placeholders(&self, recipient: &Recipient) -> Vec<String> {
+        let mut missing = Vec::new();
+        for text in self.template_texts() {
+            for key in engine::render(text, &recipient.args, Escape::None).missing {
+                if !missing.contains(&key) {
+                    missing.push(key);
+                }
+            }
+        }
+        missing
+    }
+
+    /// Template syntax problems in subject, bodies, attachment paths and headers.
+    pub fn syntax_errors(&self) -> Vec<String> {
+        self.template_texts()
+            .flat_map(engine::syntax_errors)
+            .collect()
+    }
+}
+
+fn is_cid_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')
+}
+
+/// Whether `html` contains `cid:<cid>` not followed by more of a longer ID.
+fn references_cid(html: &str, cid: &str) -> bool {
+    let needle = format!("cid:{}", cid);
+    html.match_indices(&needle).any(|(i, _)| {
+        !html[i + needle.len()..]
+            .chars()
+            .next()
+            .is_some_and(is_cid_char)
+    })
 }
 
 const TEMPLATES_FILE: &str = "templates.json";
 
-pub fn load_templates() -> Vec<EmailTemplate> {
-    match std::fs::read_to_string(TEMPLATES_FILE) {
-        Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
-        Err(_) => Vec::new(),
+/// How many earlier versions of `templates.json` are kept, as
+/// `templates.json.1` (newest) to `templates.json.5`.
+const BACKUP_COUNT: usize = 5;
+
+/// `templates.json` exists but could not be loaded. Nothing may be saved over
+/// it until the user has chosen what to do.
+#[derive(Debug)]
+pub struct LoadError {
+    pub error: String,
+    /// The newest backup that still loads, if any.
+    pub backup: Option<Backup>,
+}
+
+/// A backup of `templates.json` that loads.
+#[derive(Debug)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// When the backup was taken, in Unix seconds.
+    pub taken_at: Option<u64>,
+    pub templates: Vec<EmailTemplate>,
+}
+
+/// Loads the saved templates; a missing file just means there are none yet.
+/// A file that loads is also copied to the newest backup, once per launch.
+pub fn load_templates() -> Result<Vec<EmailTemplate>, LoadError> {
+    load_templates_from(&paths::data_file(TEMPLATES_FILE))
+}
+
+fn load_templates_from(path: &Path) -> Result<Vec<EmailTemplate>, LoadError> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(LoadError {
+                error: format!("{} could not be read: {}", path.display(), e),
+                backup: newest_backup(path),
+            });
+        }
+    };
+    match serde_json::from_str(&data) {
+        Ok(templates) => {
+            rotate_backups(path, &data);
+            Ok(templates)
+        }
+        Err(e) => Err(LoadError {
+            error: format!("{} is damaged: {}", path.display(), e),
+            backup: newest_backup(path),
+        }),
     }
 }
 
-pub fn save_templates(templates: &[EmailTemplate]) {
-    if let Ok(data) = serde_json::to_string_pretty(templates) {
-        let _ = std::fs::write(TEMPLATES_FILE, data);
+/// Saves the templates atomically, so a crash or full disk leaves either the
+/// old file or the new one, never a truncated mix.
+pub fn save_templates(templates: &[EmailTemplate]) -> Result<(), String> {
+    let path = paths::data_file(TEMPLATES_FILE);
+    let data = serde_json::to_string_pretty(templates).map_err(|e| e.to_string())?;
+    paths::write_atomic(&path, data.as_bytes())
+        .map_err(|e| format!("could not save {}: {}", path.display(), e))
+}
+
+/// Renames an unloadable `templates.json` to `templates.json.damaged-<unix
+/// time>`, so starting over or restoring a backup doesn't destroy it.
+pub fn set_aside_templates() -> Result<PathBuf, String> {
+    set_aside(&paths::data_file(TEMPLATES_FILE))
+}
+
+fn set_aside(path: &Path) -> Result<PathBuf, String> {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let aside = with_suffix(path, &format!(".damaged-{}", secs));
+    std::fs::rename(path, &aside)
+        .map_err(|e| format!("could not move {} aside: {}", path.display(), e))?;
+    Ok(aside)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn backup_path(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".{}", n))
+}
+
+/// Shifts the backups along, dropping the oldest, and stores `data` as the
+/// newest, unless the newest already holds exactly that. Best effort: a
+/// failed backup must not stop the app from starting.
+fn rotate_backups(path: &Path, data: &str) {
+    let newest = backup_path(path, 1);
+    if std::fs::read_to_string(&newest).is_ok_and(|backup| backup == data) {
+        return;
+    }
+    for n in (1..BACKUP_COUNT).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            let _ = std::fs::rename(&from, backup_path(path, n + 1));
+        }
     }
+    let _ = paths::write_atomic(&newest, data.as_bytes());
+}
+
+fn newest_backup(path: &Path) -> Option<Backup> {
+    (1..=BACKUP_COUNT).find_map(|n| {
+        let backup = backup_path(path, n);
+        let templates = serde_json::from_str(&std::fs::read_to_string(&backup).ok()?).ok()?;
+        let taken_at = std::fs::metadata(&backup)
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+        Some(Backup { path: backup, taken_at, templates })
+    })
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duplicate_starts_a_fresh_campaign() {
+        let mut template = EmailTemplate::new("Launch".to_string());
+        template.recipients = vec![
+            Recipient {
+                email: "a@example.com".to_string(),
+                status: SendStatus::Sent { at: 1, message_id: "<1@example.com>".to_string() },
+                ..Default::default()
+            },
+            Recipient {
+                email: "b@example.com".to_string(),
+                status: SendStatus::Failed { at: 2, error: "550".to_string() },
+                ..Default::default()
+            },
+        ];
+
+        let copy = template.duplicate();
+        assert_ne!(copy.id, template.id);
+        assert_eq!(copy.name, "Launch - copy");
+        assert_eq!(copy.unsent_recipients(), vec![0, 1]);
+        assert!(copy.recipients.iter().all(|r| r.status == SendStatus::Pending));
+        // The original keeps its history
+        assert!(template.recipients[0].status.is_sent());
+    }
+
+    #[test]
+    fn unsubscribe_placeholders_are_percent_encoded() {
+        let mut template = EmailTemplate::new("Launch".to_string());
+        template.unsubscribe.url = "https://example.com/unsub?id={id}".to_string();
+        template.unsubscribe.mailto = "unsub@example.com?subject=unsub-{id}".to_string();
+        let mut recipient = Recipient::default();
+        recipient.args.insert("id".to_string(), "a b&c+d#e?f".to_string());
+
+        let headers = template.render_headers(&recipient).unwrap();
+        assert_eq!(
+            headers,
+            vec![(
+                "List-Unsubscribe".to_string(),
+                "<https://example.com/unsub?id=a%20b%26c%2Bd%23e%3Ff>, \
+                 <mailto:unsub@example.com?subject=unsub-a%20b%26c%2Bd%23e%3Ff>"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn reply_to_is_reserved() {
+        let mut template = EmailTemplate::new("Launch".to_string());
+        template.headers.push(CustomHeader {
+            name: "Reply-To".to_string(),
+            value: "other@example.com".to_string(),
+        });
+        assert!(template.render_headers(&Recipient::default()).is_err());
+    }
+
+    #[test]
+    fn attachment_paths_with_missing_values_are_reported() {
+        let mut template = EmailTemplate::new("Invoices".to_string());
+        template.attachment_paths = vec!["invoices/{id}.pdf".into(), "terms.pdf".into()];
+        let recipient = |id: Option<&str>| Recipient {
+            email: "a@example.com".to_string(),
+            args: id.map(|id| HashMap::from([("id".to_string(), id.to_string())])).unwrap_or_default(),
+            attachments: vec!["extra.pdf".into()],
+            ..Default::default()
+        };
+
+        for missing in [recipient(None), recipient(Some(""))] {
+            assert_eq!(
+                template.unresolved_attachment_paths(&missing),
+                vec!["attachment invoices/{id}.pdf has no value for {id}"]
+            );
+            assert_eq!(
+                template.attachments_for(&missing),
+                vec![PathBuf::from("terms.pdf"), PathBuf::from("extra.pdf")]
+            );
+        }
+
+        let found = recipient(Some("1042"));
+        assert!(template.unresolved_attachment_paths(&found).is_empty());
+        assert_eq!(
+            template.attachments_for(&found),
+            vec![PathBuf::from("invoices/1042.pdf"), PathBuf::from("terms.pdf"), PathBuf::from("extra.pdf")]
+        );
+        // A default in the path counts as a value
+        template.attachment_paths = vec!["invoices/{id|all}.pdf".into()];
+        assert!(template.unresolved_attachment_paths(&recipient(None)).is_empty());
+    }
+
+    /// A fresh directory for a test's templates.json and its backups.
+    fn temp_templates() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("email-senderr-templates-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join(TEMPLATES_FILE)
+    }
+
+    fn saved(names: &[&str]) -> String {
+        let templates: Vec<EmailTemplate> = names.iter().map(|n| EmailTemplate::new(n.to_string())).collect();
+        serde_json::to_string(&templates).unwrap()
+    }
+
+    fn names(templates: &[EmailTemplate]) -> Vec<&str> {
+        templates.iter().map(|t| t.name.as_str()).collect()
+    }
+
+    #[test]
+    fn backups_shift_along_and_drop_the_oldest() {
+        let path = temp_templates();
+        for n in 1..=BACKUP_COUNT + 2 {
+            std::fs::write(&path, saved(&[&format!("v{}", n)])).unwrap();
+            load_templates_from(&path).unwrap();
+            // Loading unchanged data again doesn't push out an older backup
+            load_templates_from(&path).unwrap();
+        }
+        let in_backup = |n: usize| {
+            let data = std::fs::read_to_string(backup_path(&path, n)).unwrap();
+            let templates: Vec<EmailTemplate> = serde_json::from_str(&data).unwrap();
+            templates[0].name.clone()
+        };
+        assert_eq!(in_backup(1), "v7");
+        assert_eq!(in_backup(BACKUP_COUNT), "v3");
+        assert!(!backup_path(&path, BACKUP_COUNT + 1).exists());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn damaged_file_is_never_overwritten() {
+        let path = temp_templates();
+        let good = saved(&["good"]);
+        std::fs::write(&path, &good).unwrap();
+        load_templates_from(&path).unwrap();
+        std::fs::write(&path, "[{\"name\": trunc").unwrap();
+
+        let problem = load_templates_from(&path).unwrap_err();
+        assert!(problem.error.contains("is damaged"), "{}", problem.error);
+        // Neither the file nor the backups were touched by the failed load
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[{\"name\": trunc");
+        assert_eq!(std::fs::read_to_string(backup_path(&path, 1)).unwrap(), good);
+        assert!(!backup_path(&path, 2).exists());
+
+        // Setting it aside keeps the damaged contents under a new name
+        let aside = set_aside(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(&aside).unwrap(), "[{\"name\": trunc");
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn restore_offers_the_newest_backup_that_loads() {
+        let path = temp_templates();
+        std::fs::write(&path, "not json").unwrap();
+        std::fs::write(backup_path(&path, 1), "also not json").unwrap();
+        std::fs::write(backup_path(&path, 2), saved(&["older"])).unwrap();
+        std::fs::write(backup_path(&path, 3), saved(&["oldest"])).unwrap();
+
+        let backup = load_templates_from(&path).unwrap_err().backup.unwrap();
+        assert_eq!(backup.path, backup_path(&path, 2));
+        assert_eq!(names(&backup.templates), vec!["older"]);
+        assert!(backup.taken_at.is_some());
+
+        // A missing file just means there are no templates yet
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_templates_from(&path).unwrap().is_empty());
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,326 @@
+use crate::config::SmtpConfig;
+use crate::email::{dkim_config, encoded_size, estimated_attachments_size, format_size};
+use crate::paths;
+use crate::template::{parse_address_list, EmailTemplate, InlineImage};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Problems found before a bulk send.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// Problems that affect every message, such as a missing attachment.
+    pub template_issues: Vec<String>,
+    /// Problems with individual recipients, by index into `template.recipients`.
+    pub recipient_issues: Vec<(usize, String)>,
+}
+
+impl ValidationReport {
+    pub fn is_clean(&self) -> bool {
+        self.template_issues.is_empty() && self.recipient_issues.is_empty()
+    }
+
+    /// The `targets` without any flagged recipient. Empty when the template
+    /// itself has problems, since every message would be affected.
+    pub fn valid_subset(&self, targets: &[usize]) -> Vec<usize> {
+        if !self.template_issues.is_empty() {
+            return Vec::new();
+        }
+        targets
+            .iter()
+            .copied()
+            .filter(|i| !self.recipient_issues.iter().any(|(r, _)| r == i))
+            .collect()
+    }
+}
+
+/// Checks the template and the recipients at `targets` for template syntax
+/// errors, missing attachment and inline image files (shared or resolved per
+/// recipient), unparsable To/Cc/Bcc addresses, duplicate recipients,
+/// placeholders left missing or empty, invalid custom or unsubscribe headers,
+/// an unusable DKIM key and attachments over the sender profile's size limit.
+pub fn validate(config: &SmtpConfig, template: &EmailTemplate, targets: &[usize]) -> ValidationReport {
+    let mut report = ValidationReport {
+        template_issues: template.syntax_errors(),
+        ..Default::default()
+    };
+
+    // Paths with placeholders differ per recipient and are checked below
+    let shared: Vec<PathBuf> = template
+        .attachment_paths
+        .iter()
+        .filter(|p| !p.to_string_lossy().contains('{'))
+        .map(|p| paths::data_path(p))
+        .collect();
+    for path in &shared {
+        if !path.is_file() {
+            report
+                .template_issues
+                .push(format!("attachment not found: {}", path.display()));
+        }
+    }
+
+    // A key that doesn't load would fail every message
+    if let Some(dkim) = &config.dkim
+        && let Err(e) = dkim_config(dkim)
+    {
+        report.template_issues.push(e);
+    }
+
+    let header_issues = template.header_issues();
+    report.template_issues.extend(header_issues.iter().cloned());
+
+    for (field, list) in [("template Cc", &template.cc), ("template Bcc", &template.bcc)] {
+        if let Err(e) = parse_address_list(list) {
+            report.template_issues.push(format!("{}: {}", field, e));
+        }
+    }
+
+    for image in template.referenced_images(&template.body) {
+        if !image.file().is_file() {
+            report.template_issues.push(format!(
+                "inline image '{}' not found: {}",
+                image.cid,
+                image.file().display()
+            ));
+        }
+    }
+    for cid in template.unknown_image_references() {
+        report
+            .template_issues
+            .push(format!("body refers to unknown inline image 'cid:{}'", cid));
+    }
+
+    let limit = config.attachment_limit();
+    let shared_size: u64 = shared
+        .iter()
+        .cloned()
+        .chain(template.referenced_images(&template.body).into_iter().map(InlineImage::file))
+        .map(|path| encoded_size(std::fs::metadata(path).map(|m| m.len()).unwrap_or(0)))
+        .sum();
+    let shared_too_big = limit.is_some_and(|limit| shared_size > limit);
+    if let Some(limit) = limit
+        && shared_too_big
+    {
+        report.template_issues.push(format!(
+            "attachments are about {} once encoded, over the {} limit",
+            format_size(shared_size),
+            format_size(limit)
+        ));
+    }
+
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for &i in targets {
+        let Some(recipient) = template.recipients.get(i) else {
+            continue;
+        };
+
+        if let Err(e) = recipient.to_mailboxes() {
+            report.recipient_issues.push((i, format!("To: {}", e)));
+        }
+        for (field, list) in [("Cc", &recipient.cc), ("Bcc", &recipient.bcc)] {
+            if let Err(e) = parse_address_list(list) {
+                report.recipient_issues.push((i, format!("{}: {}", field, e)));
+            }
+        }
+
+        // Header name problems are already reported for the whole template
+        if header_issues.is_empty()
+            && let Err(e) = template.render_headers(recipient)
+        {
+            report.recipient_issues.push((i, e));
+        }
+
+        for issue in template.unresolved_attachment_paths(recipient) {
+            report.recipient_issues.push((i, issue));
+        }
+        for path in template.attachments_for(recipient) {
+            if !shared.contains(&path) && !path.is_file() {
+                report
+                    .recipient_issues
+                    .push((i, format!("attachment not found: {}", path.display())));
+            }
+        }
+        if let Some(limit) = limit
+            && !shared_too_big
+        {
+            let size = estimated_attachments_size(template, recipient);
+            if size > limit {
+                report.recipient_issues.push((
+                    i,
+                    format!(
+                        "attachments are about {} once encoded, over the {} limit",
+                        format_size(size),
+                        format_size(limit)
+                    ),
+                ));
+            }
+        }
+
+        let key = recipient.primary_address();
+        if let Some(&first) = seen.get(&key) {
+            report.recipient_issues.push((
+                i,
+                format!("duplicate of recipient #{} ({})", first + 1, recipient.email),
+            ));
+        } else {
+            seen.insert(key, i);
+        }
+
+        let missing = template.unresolved_placeholders(recipient);
+        if !missing.is_empty() {
+            report.recipient_issues.push((
+                i,
+                format!(
+                    "missing or empty value for {}",
+                    missing
+                        .iter()
+                        .map(|k| format!("{{{}}}", k))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            ));
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Recipient;
+
+    fn config() -> SmtpConfig {
+        SmtpConfig::new(
+            "Test".to_string(),
+            "localhost".to_string(),
+            "sender@example.com".to_string(),
+            "Sender".to_string(),
+        )
+    }
+
+    fn template(emails: &[&str]) -> EmailTemplate {
+        let mut template = EmailTemplate::new("Test".to_string());
+        template.subject = "Hello {name}".to_string();
+        template.body = "<p>Hi</p>".to_string();
+        template.recipients = emails
+            .iter()
+            .map(|email| Recipient {
+                email: email.to_string(),
+                args: HashMap::from([("name".to_string(), "Jane".to_string())]),
+                ..Default::default()
+            })
+            .collect();
+        template
+    }
+
+    fn check(template: &EmailTemplate) -> (ValidationReport, Vec<usize>) {
+        let targets: Vec<usize> = (0..template.recipients.len()).collect();
+        let report = validate(&config(), template, &targets);
+        let valid = report.valid_subset(&targets);
+        (report, valid)
+    }
+
+    #[test]
+    fn duplicate_primary_addresses_keep_the_first() {
+        let template = template(&["a@example.com", "Jane <A@Example.com>, b@example.com", "b@example.com"]);
+        let (report, valid) = check(&template);
+
+        assert_eq!(valid, vec![0, 2]);
+        assert_eq!(
+            report.recipient_issues,
+            vec![(1, "duplicate of recipient #1 (Jane <A@Example.com>, b@example.com)".to_string())]
+        );
+    }
+
+    #[test]
+    fn missing_per_recipient_attachment_only_drops_that_recipient() {
+        let dir = std::env::temp_dir().join(format!("email-senderr-validate-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let present = dir.join("present.pdf");
+        std::fs::write(&present, b"%PDF").unwrap();
+        let missing = dir.join("missing.pdf");
+
+        let mut template = template(&["a@example.com", "b@example.com"]);
+        template.recipients[0].attachments = vec![present];
+        template.recipients[1].attachments = vec![missing.clone()];
+        let (report, valid) = check(&template);
+
+        assert!(report.template_issues.is_empty());
+        assert_eq!(valid, vec![0]);
+        assert_eq!(
+            report.recipient_issues,
+            vec![(1, format!("attachment not found: {}", missing.display()))]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn attachment_paths_with_missing_values_are_not_looked_up() {
+        let mut template = template(&["a@example.com"]);
+        template.attachment_paths = vec!["invoices/{id}.pdf".into()];
+        let (report, valid) = check(&template);
+
+        assert!(valid.is_empty());
+        assert_eq!(
+            report.recipient_issues,
+            vec![
+                (0, "attachment invoices/{id}.pdf has no value for {id}".to_string()),
+                (0, "missing or empty value for {id}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_placeholder_values_count_as_missing() {
+        let mut template = template(&["a@example.com", "b@example.com", "c@example.com"]);
+        template.recipients[1].args.insert("name".to_string(), String::new());
+        template.recipients[2].args.clear();
+        let (report, valid) = check(&template);
+
+        assert_eq!(valid, vec![0]);
+        assert_eq!(
+            report.recipient_issues,
+            vec![
+                (1, "missing or empty value for {name}".to_string()),
+                (2, "missing or empty value for {name}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_cc_and_bcc() {
+        let mut template = template(&["a@example.com", "b@example.com", "c@example.com"]);
+        template.recipients[1].cc = "not an address".to_string();
+        template.recipients[2].bcc = "ok@example.com, @nowhere".to_string();
+        let (report, valid) = check(&template);
+
+        assert!(report.template_issues.is_empty());
+        assert_eq!(valid, vec![0]);
+        let fields: Vec<(usize, &str)> = report
+            .recipient_issues
+            .iter()
+            .map(|(i, issue)| (*i, issue.split(':').next().unwrap()))
+            .collect();
+        assert_eq!(fields, vec![(1, "Cc"), (2, "Bcc")]);
+
+        // A bad template-wide copy affects every message
+        template.recipients[1].cc.clear();
+        template.recipients[2].bcc.clear();
+        template.cc = "archive@".to_string();
+        let (report, valid) = check(&template);
+        assert!(report.template_issues[0].starts_with("template Cc: "));
+        assert!(valid.is_empty());
+    }
+
+    #[test]
+    fn template_syntax_errors_block_every_recipient() {
+        let mut template = template(&["a@example.com", "b@example.com"]);
+        template.body = "<p>{#if name}Hi</p>".to_string();
+        let (report, valid) = check(&template);
+
+        assert_eq!(report.template_issues, vec!["{#if name} is never closed with {/if}"]);
+        assert!(report.recipient_issues.is_empty());
+        assert!(valid.is_empty());
+    }
+}